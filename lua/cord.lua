@@ -10,18 +10,58 @@ cord.config = {
     interval = 1500,
     reset_on_idle = false,
     reset_on_change = false,
+    reconnect_interval = 5000,
+    connect_attempts = 4,
+    connect_delay = 500,
+    scope = 'session',
+    ignore_timestamp_changes = false,
   },
   editor = {
     image = nil,
     client = 'neovim',
     tooltip = 'The Superior Text Editor',
+    id = nil,
+    images = {},
+    tooltips = {},
   },
   display = {
     show_time = true,
     show_repository = true,
     show_cursor_position = false,
+    show_symbol = false,
     swap_fields = false,
+    layout = nil,
+    require_focus = false,
+    use_package_root = false,
+    show_workspace_path = false,
+    relative_filename = false,
+    clear_on_empty = false,
+    clear_on_focus_lost = false,
+    recompute_workspace_always = false,
+    on_missing_icon = 'text',
+    default_icon = nil,
+    default_tooltip = nil,
+    unnamed_icon = nil,
+    show_last_command = false,
+    last_command_duration = 5000,
+    sticky_code_presence = false,
+    recent_files_ticker = false,
+    recent_files_interval = 10000,
+    workspace_privacy = 'off',
+    workspace_placeholder = 'a project',
+    readonly_debounce = 0,
+    max_details_length = 128,
+    cursor_position_format = ':{line}:{col}',
     workspace_blacklist = {},
+    path_blacklist = {},
+    ignored_filetypes = {},
+    ignored_files = {},
+    icon_variants = {},
+    filetype_aliases = {},
+    assets_url = nil,
+    assets_version = nil,
+    workspace_markers = { '.git', '.svn', '.hg' },
+    lsp_root_markers = { 'pyproject.toml', 'package.json', 'Cargo.toml', 'go.mod', '.luarc.json', 'compile_commands.json' },
   },
   lsp = {
     show_problem_count = false,
@@ -34,6 +74,7 @@ cord.config = {
     disable_on_focus = true,
     text = 'Idle',
     tooltip = '💤',
+    image = nil,
   },
   text = {
     viewing = 'Viewing {}',
@@ -41,7 +82,35 @@ cord.config = {
     file_browser = 'Browsing files in {}',
     plugin_manager = 'Managing plugins in {}',
     lsp_manager = 'Configuring LSP in {}',
+    dashboard = 'Browsing {}',
     workspace = 'In {}',
+    languages_delimiter = ', ',
+    languages_max = 3,
+    problems = ' - {} problems',
+    problem = ' - {} problem',
+    dirty = '{} changed',
+    config_editing = 'Configuring Neovim',
+    unnamed = 'Editing a new file',
+    focus = 'Focusing',
+  },
+  streaming = {
+    text = 'Live on stream',
+    url = nil,
+  },
+  git = {
+    cache_ttl = 5000,
+    remote = 'origin',
+    hosts = {},
+    changes = false,
+  },
+  notifications = {
+    connection = false,
+    activity_errors = false,
+    on_connection_event = nil,
+  },
+  discord = {
+    ipc_path = nil,
+    build = nil,
   },
   buttons = {
     {
@@ -56,29 +125,101 @@ local discord
 local connection_tries = 0
 local timer = vim.loop.new_timer()
 local enabled = false
+local disabled = false
 local is_focused = true
 local force_idle = false
 local problem_count = -1
 local last_updated = os.clock()
 local last_presence
 local is_blacklisted
+local is_streaming = false
+local last_command
+local recent_files = {}
+local last_ticked = 0
+local connection_events = {
+  [0] = { name = 'connected', message = 'Connected to Discord' },
+  [1] = { name = 'disconnected', message = 'Disconnected from Discord' },
+  [2] = { name = 'reconnecting', message = 'Reconnecting to Discord' },
+  [3] = { name = 'not_installed', message = 'Discord does not seem to be installed, run :CordConnect to try again' },
+  [4] = { name = 'lost', message = 'Lost connection to Discord, reconnecting' },
+  [5] = { name = 'handshake_failed', message = 'Discord rejected the connection' },
+  [6] = { name = 'rejected', message = 'Discord rejected the activity' },
+}
 
 local function connect(config)
+  -- the switch lives in the config created on connect, so it is kept here until then
+  if disabled then return end
   discord.init(
     ffi.new('InitArgs',
       config.editor.client,
       config.editor.image,
       config.editor.tooltip,
+      utils.get_editor_version(),
       config.idle.text,
       config.idle.tooltip,
+      config.idle.image,
       config.text.viewing,
       config.text.editing,
       config.text.file_browser,
       config.text.plugin_manager,
       config.text.lsp_manager,
       config.text.workspace,
+      config.text.config_editing,
       vim.fn.getcwd(),
-      config.display.swap_fields
+      vim.fn.stdpath('config'),
+      utils.encode_map(config.display.icon_variants),
+      config.display.assets_url,
+      config.display.assets_version,
+      table.concat(config.display.workspace_markers or {}, '\n'),
+      table.concat(config.display.lsp_root_markers or {}, '\n'),
+      config.streaming.text,
+      config.streaming.url,
+      config.text.languages_delimiter,
+      config.text.languages_max,
+      config.text.problems,
+      config.text.problem,
+      config.timer.reconnect_interval,
+      config.timer.connect_attempts,
+      config.timer.connect_delay,
+      config.timer.scope,
+      config.git.cache_ttl,
+      config.git.remote,
+      utils.encode_map(config.git.hosts),
+      config.display.readonly_debounce,
+      config.display.max_details_length,
+      config.display.cursor_position_format,
+      config.display.swap_fields,
+      config.display.layout,
+      config.display.require_focus,
+      config.display.use_package_root,
+      config.display.clear_on_empty,
+      config.display.recompute_workspace_always,
+      config.display.on_missing_icon,
+      config.display.default_icon,
+      config.display.default_tooltip,
+      config.display.sticky_code_presence,
+      config.display.recent_files_ticker,
+      config.display.workspace_privacy,
+      config.display.workspace_placeholder,
+      config.display.show_workspace_path,
+      config.timer.ignore_timestamp_changes,
+      config.text.dirty,
+      config.notifications.activity_errors,
+      config.discord.ipc_path,
+      config.discord.build,
+      config.display.relative_filename,
+      utils.encode_map(config.display.filetype_aliases),
+      config.editor.id or utils.get_editor(),
+      utils.encode_map(config.editor.images),
+      utils.encode_map(config.editor.tooltips),
+      table.concat(config.display.path_blacklist or {}, '\n'),
+      table.concat(config.display.ignored_filetypes or {}, '\n'),
+      table.concat(config.display.ignored_files or {}, '\n'),
+      config.text.dashboard,
+      config.text.unnamed,
+      config.display.unnamed_icon,
+      config.git.changes,
+      config.display.last_command_duration
     ),
     config.display.show_repository and ffi.new(
       'Buttons',
@@ -90,6 +231,73 @@ local function connect(config)
   )
 end
 
+-- returns true if Discord is not installed, in which case no further attempts are made until an explicit retry
+local function poll_connection_events(config)
+  local not_installed = false
+  local event = connection_events[discord.poll_connection_event()]
+  while event do
+    if event.name == 'handshake_failed' or event.name == 'rejected' then
+      -- always reported, otherwise the presence would silently never appear
+      local reason = discord.get_last_error()
+      local level = event.name == 'rejected' and vim.log.levels.WARN or vim.log.levels.ERROR
+      vim.notify('[cord.nvim] ' .. event.message .. (reason ~= nil and (': ' .. ffi.string(reason)) or ''), level)
+    elseif config.notifications.connection then
+      vim.notify('[cord.nvim] ' .. event.message, vim.log.levels.INFO)
+    end
+    if config.notifications.on_connection_event then
+      config.notifications.on_connection_event(event.name)
+    end
+    if event.name == 'not_installed' then
+      not_installed = true
+    end
+    if event.name == 'lost' then
+      -- the timer keeps running and waits for the new connection like on startup
+      discord.disconnect()
+      connect(config)
+      connection_tries = 0
+      last_presence = nil
+    end
+    event = connection_events[discord.poll_connection_event()]
+  end
+
+  if not_installed then
+    timer:stop()
+    connection_tries = 0
+    enabled = false
+    last_presence = nil
+  end
+  return not_installed
+end
+
+local function track_recent_file()
+  local name = vim.fn.expand('%:t')
+  if name == '' then
+    return
+  end
+
+  for i, file in ipairs(recent_files) do
+    if file == name then
+      table.remove(recent_files, i)
+      break
+    end
+  end
+  table.insert(recent_files, 1, name)
+  recent_files[6] = nil
+end
+
+local function should_tick(config)
+  if not discord.needs_periodic_refresh() then
+    return false
+  end
+  local interval = config.display.recent_files_ticker and config.display.recent_files_interval or 0
+  if vim.loop.now() - last_ticked < interval then
+    return false
+  end
+
+  last_ticked = vim.loop.now()
+  return true
+end
+
 local function should_update_presence(current_presence)
   return not last_presence or
     current_presence.cursor_line ~= last_presence.cursor_line or
@@ -97,6 +305,14 @@ local function should_update_presence(current_presence)
     current_presence.name ~= last_presence.name or
     current_presence.type ~= last_presence.type or
     current_presence.readonly ~= last_presence.readonly or
+    current_presence.streaming ~= last_presence.streaming or
+    current_presence.toolchain ~= last_presence.toolchain or
+    current_presence.symbol ~= last_presence.symbol or
+    current_presence.empty ~= last_presence.empty or
+    current_presence.languages ~= last_presence.languages or
+    current_presence.last_command ~= last_presence.last_command or
+    current_presence.line_count ~= last_presence.line_count or
+    current_presence.colorscheme ~= last_presence.colorscheme or
     current_presence.problem_count ~= last_presence.problem_count
 end
 
@@ -114,10 +330,14 @@ local function update_idle_presence(config)
       ffi.new(
         'PresenceArgs',
         '',
+        '',
         'Cord.idle',
         nil,
 	0,
-        false
+        false,
+        is_streaming,
+        nil,
+        nil
       )
     )
     return true
@@ -143,7 +363,17 @@ local function update_idle_presence(config)
 end
 
 local function update_presence(config, initial)
+  if poll_connection_events(config) then
+    return
+  end
+  if config.display.clear_on_focus_lost and not is_focused then
+    return
+  end
+  discord.flush_presence()
   if is_blacklisted then
+    -- repeated clears are deduplicated on the Rust side
+    discord.update_presence(ffi.new('PresenceArgs', { should_clear = true }))
+    last_presence = nil
     return
   end
 
@@ -151,11 +381,21 @@ local function update_presence(config, initial)
   problem_count = utils.get_problem_count(config) or -1
   local current_presence = {
     name = vim.fn.expand('%:t'),
+    path = vim.fn.expand('%:p'),
     type = vim.bo.filetype,
     readonly = vim.bo.readonly,
     cursor_line = cursor[1],
     cursor_col = cursor[2] + 1,
-    problem_count = problem_count
+    problem_count = problem_count,
+    streaming = is_streaming,
+    toolchain = utils.get_toolchain(),
+    symbol = utils.get_symbol(config),
+    empty = utils.is_empty_session(),
+    languages = utils.get_languages(config),
+    last_command = last_command,
+    encoding = vim.bo.fileencoding,
+    line_count = utils.get_line_count(config),
+    colorscheme = vim.g.colors_name
   }
 
   if current_presence.type == '' then
@@ -166,9 +406,11 @@ local function update_presence(config, initial)
     end
   end
 
-  if should_update_presence(current_presence) then
+  if should_update_presence(current_presence) or should_tick(config) then
     force_idle = false
     last_updated = os.clock()
+    -- sent once, how long it stays visible is tracked on the Rust side
+    last_command = nil
     if config.display.show_time and config.timer.reset_on_change then
       discord.update_time()
     end
@@ -185,10 +427,22 @@ local function update_presence(config, initial)
         ffi.new(
           'PresenceArgs',
           current_presence.name,
+          current_presence.path,
           current_presence.type,
           cursor_pos,
           problem_count,
-          current_presence.readonly
+          current_presence.readonly,
+          current_presence.streaming,
+          current_presence.toolchain,
+          current_presence.symbol,
+          current_presence.empty,
+          current_presence.languages,
+          current_presence.last_command,
+          current_presence.encoding,
+          config.display.recent_files_ticker and table.concat(recent_files, '\n') or nil,
+          current_presence.line_count,
+          false,
+          current_presence.colorscheme
         )
       )
     else
@@ -196,10 +450,22 @@ local function update_presence(config, initial)
         ffi.new(
           'PresenceArgs',
           current_presence.name,
+          current_presence.path,
           current_presence.type,
           cursor_pos,
           problem_count,
-          current_presence.readonly
+          current_presence.readonly,
+          current_presence.streaming,
+          current_presence.toolchain,
+          current_presence.symbol,
+          current_presence.empty,
+          current_presence.languages,
+          current_presence.last_command,
+          current_presence.encoding,
+          config.display.recent_files_ticker and table.concat(recent_files, '\n') or nil,
+          current_presence.line_count,
+          false,
+          current_presence.colorscheme
         )
       )
     end
@@ -219,6 +485,7 @@ local function update_presence(config, initial)
         connection_tries = 0
         timer:stop()
         discord.disconnect()
+        poll_connection_events(config)
         enabled = false
         last_presence = nil
       end
@@ -258,10 +525,67 @@ function cord.setup(userConfig)
   end
 end
 
+-- returns the activity json the given file would produce, without switching buffers
+function cord.preview_presence(path)
+  if not discord then
+    return
+  end
+
+  local preview = discord.get_presence_preview(
+    ffi.new(
+      'PresenceArgs',
+      vim.fn.fnamemodify(path, ':t'),
+      vim.fn.fnamemodify(path, ':p'),
+      vim.filetype.match({ filename = path }) or 'Cord.unknown',
+      nil,
+      -1,
+      false,
+      is_streaming,
+      utils.get_toolchain(),
+      nil
+    )
+  )
+  if preview ~= nil then
+    return ffi.string(preview)
+  end
+end
+
+-- shows the given activity table, in Discord's shape, until the next presence update
+-- returns false if it is not a valid activity
+function cord.set_raw_activity(activity)
+  if not discord then
+    return false
+  end
+
+  local ok, json = pcall(vim.json.encode, activity)
+  return ok and discord.update_raw_presence(json)
+end
+
 function cord.setup_autocmds(config)
   vim.api.nvim_create_autocmd('DirChanged', { callback = function() is_blacklisted = utils.array_contains(config.display.workspace_blacklist, ffi.string(discord.update_workspace(vim.fn.getcwd()))) end })
-  vim.api.nvim_create_autocmd('FocusGained', { callback = function() is_focused = true; last_presence = nil end })
-  vim.api.nvim_create_autocmd('FocusLost', { callback = function() is_focused = false end })
+  vim.api.nvim_create_autocmd('FocusGained', { callback = function() is_focused = true; last_presence = nil; discord.focus_gained() end })
+  vim.api.nvim_create_autocmd('FocusLost', {
+    callback = function()
+      is_focused = false
+      if config.display.clear_on_focus_lost then
+        -- the connection stays open, FocusGained sends the presence again
+        discord.clear_presence()
+        last_presence = nil
+      end
+    end
+  })
+  if config.display.recent_files_ticker then
+    vim.api.nvim_create_autocmd('BufWritePost', { callback = track_recent_file })
+  end
+  if config.display.show_last_command then
+    vim.api.nvim_create_autocmd('CmdlineLeave', {
+      callback = function()
+        if vim.v.event.cmdtype == ':' and not vim.v.event.abort then
+          last_command = vim.fn.getcmdline()
+        end
+      end
+    })
+  end
 end
 
 function cord.setup_usercmds(config)
@@ -282,6 +606,7 @@ function cord.setup_usercmds(config)
   vim.api.nvim_create_user_command('CordDisconnect', function()
     timer:stop()
     discord.disconnect()
+    poll_connection_events(config)
     enabled = false
     last_presence = nil
   end, {})
@@ -324,6 +649,84 @@ function cord.setup_usercmds(config)
     force_idle = true
   end, {})
 
+  vim.api.nvim_create_user_command('CordFocus', function(opts)
+    discord.enter_focus_mode(opts.args ~= '' and opts.args or config.text.focus)
+  end, { nargs = '?' })
+
+  vim.api.nvim_create_user_command('CordUnfocus', function()
+    discord.exit_focus_mode()
+    last_presence = nil
+  end, {})
+
+  vim.api.nvim_create_user_command('CordCountdown', function(opts)
+    local minutes = tonumber(opts.args)
+    discord.set_end_time(minutes and (os.time() + minutes * 60) * 1000 or 0, true)
+    last_presence = nil
+  end, { nargs = '?' })
+
+  vim.api.nvim_create_user_command('CordToggleStreaming', function()
+    is_streaming = not is_streaming
+  end, {})
+
+  vim.api.nvim_create_user_command('CordEnable', function()
+    disabled = false
+    discord.set_enabled(true)
+    last_presence = nil
+    connect(config)
+    -- the presence was cleared on disable, sending it again right away
+    start_timer(config)
+  end, {})
+
+  vim.api.nvim_create_user_command('CordDisable', function()
+    disabled = true
+    discord.set_enabled(false)
+    timer:stop()
+  end, {})
+
+  vim.api.nvim_create_user_command('CordConfig', function(opts)
+    local json = discord.get_config_json(not opts.bang)
+    if json == nil then
+      vim.notify('[cord.nvim] Not connected to Discord', vim.log.levels.WARN)
+      return
+    end
+    print(ffi.string(json))
+  end, { bang = true })
+
+  vim.api.nvim_create_user_command('CordDoctor', function()
+    local urls = discord.get_asset_urls()
+    if urls == nil then
+      vim.notify('[cord.nvim] Not connected to Discord', vim.log.levels.WARN)
+      return
+    end
+    if vim.fn.executable('curl') == 0 then
+      vim.notify('[cord.nvim] CordDoctor requires curl', vim.log.levels.WARN)
+      return
+    end
+
+    local pending = 0
+    local failed = {}
+    for url in ffi.string(urls):gmatch('[^\n]+') do
+      if url:match('^https?://') then
+        pending = pending + 1
+        vim.fn.jobstart({ 'curl', '-sfIL', '-o', vim.fn.has('win32') == 1 and 'NUL' or '/dev/null', url }, {
+          on_exit = function(_, code)
+            if code ~= 0 then
+              table.insert(failed, url)
+            end
+            pending = pending - 1
+            if pending == 0 then
+              if #failed == 0 then
+                vim.notify('[cord.nvim] All assets are reachable', vim.log.levels.INFO)
+              else
+                vim.notify('[cord.nvim] Unreachable assets:\n' .. table.concat(failed, '\n'), vim.log.levels.WARN)
+              end
+            end
+          end
+        })
+      end
+    end
+  end, {})
+
   vim.api.nvim_create_user_command('CordUnidle', function()
     force_idle = false
     last_updated = os.clock()
@@ -36,23 +36,91 @@ local function init_discord(ffi)
       const char* client;
       const char* image;
       const char* editor_tooltip;
+      const char* editor_version;
       const char* idle_text;
       const char* idle_tooltip;
+      const char* idle_image;
       const char* viewing_text;
       const char* editing_text;
       const char* file_browser_text;
       const char* plugin_manager_text;
       const char* lsp_manager_text;
       const char* workspace_text;
+      const char* config_editing_text;
       const char* initial_path;
+      const char* config_dir;
+      const char* icon_variants;
+      const char* assets_url;
+      const char* assets_version;
+      const char* workspace_markers;
+      const char* lsp_root_markers;
+      const char* streaming_text;
+      const char* streaming_url;
+      const char* languages_delimiter;
+      unsigned int languages_max;
+      const char* problem_text;
+      const char* problem_text_singular;
+      unsigned int reconnect_interval;
+      unsigned int connect_attempts;
+      unsigned int connect_delay;
+      const char* timestamp_scope;
+      unsigned int git_cache_ttl;
+      const char* git_remote;
+      const char* git_hosts;
+      unsigned int readonly_debounce;
+      unsigned int max_details_length;
+      const char* cursor_position_format;
       const bool swap;
+      const char* layout;
+      const bool require_focus;
+      const bool use_package_root;
+      const bool clear_on_empty;
+      const bool recompute_workspace_always;
+      const char* on_missing_icon;
+      const char* default_icon;
+      const char* default_tooltip;
+      const bool sticky_code_presence;
+      const bool recent_files_ticker;
+      const char* workspace_privacy;
+      const char* workspace_placeholder;
+      const bool show_workspace_path;
+      const bool ignore_timestamp_changes;
+      const char* dirty_text;
+      const bool check_responses;
+      const char* ipc_path;
+      const char* discord_build;
+      const bool relative_filename;
+      const char* filetype_aliases;
+      const char* editor;
+      const char* editor_images;
+      const char* editor_tooltips;
+      const char* path_blacklist;
+      const char* ignored_filetypes;
+      const char* ignored_files;
+      const char* dashboard_text;
+      const char* unnamed_text;
+      const char* unnamed_icon;
+      bool git_changes;
+      uint32_t last_command_duration;
     } InitArgs;
     typedef struct {
       const char* filename;
+      const char* filepath;
       const char* filetype;
       const char* cursor_position;
       int problem_count;
       bool is_read_only;
+      bool is_streaming;
+      const char* toolchain;
+      const char* symbol;
+      bool is_empty_session;
+      const char* languages;
+      const char* last_command;
+      const char* encoding;
+      const char* recent_files;
+      int line_count;
+      bool should_clear;
+      const char* colorscheme;
     } PresenceArgs;
     typedef struct {
       const char* first_label;
@@ -74,10 +142,26 @@ local function init_discord(ffi)
       int asset_type,
       const InitArgs* args
     );
+    const char* get_presence_preview(
+      const PresenceArgs* args
+    );
+    const char* get_config_json(bool redact);
+    const char* get_asset_urls();
+    int poll_connection_event();
+    const char* get_last_error();
+    int get_last_error_code();
+    const bool needs_periodic_refresh();
+    const bool enter_focus_mode(const char* text);
+    bool exit_focus_mode();
+    const bool update_raw_presence(const char* json);
+    void focus_gained();
+    void set_enabled(bool enabled);
     void clear_presence();
+    void flush_presence();
     void disconnect();
     const char* update_workspace(const char* workspace);
     void update_time();
+    void set_end_time(uint64_t end, bool replace_start);
     const char* get_workspace();
   ]]
 
@@ -117,6 +201,110 @@ local function array_contains(arr, val)
     return false
 end
 
+local function encode_map(tbl)
+  if tbl == nil then
+    return ''
+  end
+
+  local entries = {}
+  for key, value in pairs(tbl) do
+    table.insert(entries, key .. '=' .. (type(value) == 'table' and table.concat(value, ' ') or value))
+  end
+
+  return table.concat(entries, '\n')
+end
+
+local function get_editor_version()
+  if not vim.version then
+    return
+  end
+
+  local version = vim.version()
+  return string.format('%d.%d.%d', version.major, version.minor, version.patch)
+end
+
+-- neovide and other GUIs run the same Neovim, so they are told apart by the globals they set
+local function get_editor()
+  if vim.g.neovide then
+    return 'neovide'
+  end
+  if vim.g.goneovim then
+    return 'goneovim'
+  end
+  if vim.fn.has('nvim') == 0 then
+    return 'vim'
+  end
+  return 'neovim'
+end
+
+local function get_toolchain()
+  local venv = vim.env.VIRTUAL_ENV or vim.env.CONDA_PREFIX
+  if venv then
+    return vim.fn.fnamemodify(venv, ':t') .. ' (venv)'
+  end
+
+  return vim.env.RUSTUP_TOOLCHAIN
+end
+
+local function get_symbol(config)
+  if not config.display.show_symbol then
+    return
+  end
+
+  local ok, node = pcall(vim.treesitter.get_node)
+  if not ok then
+    return
+  end
+
+  while node do
+    local node_type = node:type()
+    if node_type:find('function') or node_type:find('method') then
+      local name = node:field('name')[1]
+      if name then
+        return vim.treesitter.get_node_text(name, 0)
+      end
+    end
+    node = node:parent()
+  end
+end
+
+local function is_empty_session()
+  for _, buf in ipairs(vim.api.nvim_list_bufs()) do
+    if vim.bo[buf].buflisted and (vim.api.nvim_buf_get_name(buf) ~= '' or vim.bo[buf].modified) then
+      return false
+    end
+  end
+
+  return true
+end
+
+local function get_languages(config)
+  if not config.text.workspace or not config.text.workspace:find('{languages}', 1, true) then
+    return
+  end
+
+  local seen = {}
+  local languages = {}
+  for _, buf in ipairs(vim.api.nvim_list_bufs()) do
+    local filetype = vim.bo[buf].filetype
+    if vim.bo[buf].buflisted and filetype ~= '' and not seen[filetype] then
+      seen[filetype] = true
+      table.insert(languages, filetype)
+    end
+  end
+
+  return table.concat(languages, '\n')
+end
+
+local function get_line_count(config)
+  local uses_lines = function(text) return text and text:find('{lines}', 1, true) end
+  if uses_lines(config.text.viewing) or uses_lines(config.text.editing) then
+    return vim.api.nvim_buf_line_count(0)
+  end
+
+  return -1
+end
+
 local function get_file_extension(filename)
     for i = #filename, 1, -1 do
         if filename:sub(i, i) == '.' then
@@ -137,6 +325,12 @@ local function get_icon(config, filename, filetype)
     return icon, filetype
   end
 
+  local alias = (config.display.filetype_aliases or {})[filetype]
+  icon = alias and config.assets[alias]
+  if icon then
+    return icon, alias
+  end
+
   icon = config.assets[filename]
   if icon then
     return icon, filename
@@ -147,6 +341,13 @@ local function get_icon(config, filename, filetype)
   if icon then
     return icon, extension
   end
+
+  -- keys with wildcards are matched against the file name last, e.g. 'Dockerfile.*'
+  for pattern, value in pairs(config.assets) do
+    if pattern:find('[*?]') and vim.fn.match(filename, vim.fn.glob2regpat(pattern)) ~= -1 then
+      return value, filename
+    end
+  end
 end
 
 
@@ -155,5 +356,13 @@ return {
   validate_severity = validate_severity,
   get_problem_count = get_problem_count,
   array_contains = array_contains,
+  encode_map = encode_map,
+  get_editor_version = get_editor_version,
+  get_editor = get_editor,
+  get_toolchain = get_toolchain,
+  get_symbol = get_symbol,
+  is_empty_session = is_empty_session,
+  get_languages = get_languages,
+  get_line_count = get_line_count,
   get_icon = get_icon
 }
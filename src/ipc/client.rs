@@ -1,10 +1,22 @@
-use crate::rpc::packet::Activity;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::ipc::utils;
+use crate::rpc::packet::{Activity, Packet};
 
 #[cfg(target_os = "windows")]
 pub struct RichClient {
     pub client_id: u64,
     pub pipe: Option<std::fs::File>,
     pub last_activity: Option<Activity>,
+    pub is_cleared: bool,
+    pub rate_limit: RateLimit,
+    /// The latest update held back by the rate limit.
+    pub pending: Option<Packet>,
+    /// Whether an update that only moves the timestamp is skipped.
+    pub ignore_timestamp_changes: bool,
+    /// Whether Discord's reply to every update is read and checked.
+    pub check_responses: bool,
 }
 
 #[cfg(not(target_os = "windows"))]
@@ -12,23 +24,293 @@ pub struct RichClient {
     pub client_id: u64,
     pub pipe: Option<std::os::unix::net::UnixStream>,
     pub last_activity: Option<Activity>,
+    pub is_cleared: bool,
+    pub rate_limit: RateLimit,
+    /// The latest update held back by the rate limit.
+    pub pending: Option<Packet>,
+    /// Whether an update that only moves the timestamp is skipped.
+    pub ignore_timestamp_changes: bool,
+    /// Whether Discord's reply to every update is read and checked.
+    pub check_responses: bool,
+}
+
+/// Socket or pipe path set in the config, connected to before any other.
+pub static IPC_PATH: Mutex<Option<String>> = Mutex::new(None);
+/// Discord build set in the config, any build is accepted when unset.
+pub static DISCORD_BUILD: Mutex<Option<String>> = Mutex::new(None);
+
+/// How long a read waits for Discord to answer. Replies to updates are read
+/// on Neovim's main thread, which must not hang on a stalled Discord.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Discord accepts about five activity updates per 20 seconds and silently
+/// drops the rest. Spacing writes evenly keeps well below that, even across
+/// reconnects, instead of spending the budget on a burst.
+const RATE_LIMIT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Time of the latest activity update, allowing one write per interval.
+#[derive(Default)]
+pub struct RateLimit {
+    sent_at: Option<Instant>,
+}
+
+impl RateLimit {
+    /// Claims the next write, failing while the interval has not passed.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        if self
+            .sent_at
+            .is_some_and(|sent| now.duration_since(sent) < RATE_LIMIT_INTERVAL)
+        {
+            return false;
+        }
+
+        self.sent_at = Some(now);
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Connected = 0,
+    Disconnected = 1,
+    Reconnecting = 2,
+    NotInstalled = 3,
+    /// The connection broke and could not be re-established.
+    Lost = 4,
+    /// Discord refused the handshake, e.g. because of an invalid client ID.
+    HandshakeFailed = 5,
+    /// Discord answered an activity update with an error.
+    Rejected = 6,
+}
+
+#[derive(Debug)]
+pub enum ConnectionError {
+    /// No Discord socket could be found, Discord is most likely not running.
+    NotFound,
+    /// Discord refused the handshake, e.g. because of an invalid client ID.
+    Handshake {
+        code: Option<i64>,
+        message: String,
+    },
+    Io(std::io::Error),
+    RateLimited,
+    /// Discord closed the stream in the middle of a frame.
+    Closed,
+    /// Discord answered an activity update with an ERROR event.
+    Rejected {
+        code: Option<i64>,
+        message: String,
+    },
+}
+
+impl ConnectionError {
+    /// Identifies the kind of error across the FFI boundary.
+    #[inline(always)]
+    pub fn code(&self) -> i32 {
+        match self {
+            ConnectionError::NotFound => 1,
+            ConnectionError::Handshake { .. } => 2,
+            ConnectionError::Io(_) => 3,
+            ConnectionError::RateLimited => 4,
+            ConnectionError::Rejected { .. } => 5,
+            ConnectionError::Closed => 6,
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionError::NotFound => write!(f, "Pipe not found"),
+            ConnectionError::Handshake {
+                code: Some(code),
+                message,
+            }
+            | ConnectionError::Rejected {
+                code: Some(code),
+                message,
+            } => write!(f, "{} ({})", message, code),
+            ConnectionError::Handshake {
+                code: None,
+                message,
+            }
+            | ConnectionError::Rejected {
+                code: None,
+                message,
+            } => {
+                write!(f, "{}", message)
+            }
+            ConnectionError::Io(e) => write!(f, "{}", e),
+            ConnectionError::RateLimited => {
+                write!(f, "Rate limited by Discord")
+            }
+            ConnectionError::Closed => {
+                write!(f, "Connection closed by Discord")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {}
+
+impl From<std::io::Error> for ConnectionError {
+    fn from(error: std::io::Error) -> Self {
+        ConnectionError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Handshake,
+    Frame,
+    Close,
+    Ping,
+    Pong,
+    /// Opcodes added by future versions of Discord, skipped when read.
+    Unknown(u32),
+}
+
+impl Opcode {
+    #[inline(always)]
+    pub fn from_u32(value: u32) -> Opcode {
+        match value {
+            0 => Opcode::Handshake,
+            1 => Opcode::Frame,
+            2 => Opcode::Close,
+            3 => Opcode::Ping,
+            4 => Opcode::Pong,
+            value => Opcode::Unknown(value),
+        }
+    }
+}
+
+/// A frame received from Discord.
+pub struct Frame {
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
 }
 
 pub trait Connection {
+    /// Scans for Discord up to `attempts` times, waiting `base_delay` after
+    /// the first failed scan and twice as long after every following one.
+    /// The returned client has completed the handshake.
     fn connect(
         client_id: u64,
-    ) -> Result<RichClient, Box<dyn std::error::Error>>;
-    fn read(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+        attempts: u32,
+        base_delay: std::time::Duration,
+    ) -> Result<RichClient, ConnectionError>;
+    fn is_installed() -> bool;
+    /// Reads the next frame, skipping unknown opcodes. A CLOSE frame also
+    /// drops the connection.
+    fn read(&mut self) -> Result<Frame, ConnectionError>;
     fn write(
         &mut self,
         opcode: u32,
         data: Option<&[u8]>,
-    ) -> std::io::Result<()>;
-    fn close(&mut self) -> std::io::Result<()>;
-    fn handshake(&mut self) -> std::io::Result<()>;
-    fn update(
-        &mut self,
-        packet: &crate::rpc::packet::Packet,
-    ) -> std::io::Result<()>;
-    fn clear(&mut self) -> std::io::Result<()>;
+    ) -> Result<(), ConnectionError>;
+    /// Replaces a broken connection, failing when Discord cannot be reached.
+    fn reconnect(&mut self) -> Result<(), ConnectionError>;
+    /// Says goodbye to Discord and drops the connection. Best effort, a
+    /// connection that is already dead is dropped without an error.
+    fn close(&mut self) -> Result<(), ConnectionError>;
+    fn handshake(&mut self) -> Result<(), ConnectionError>;
+}
+
+// What is sent and when is the same on every platform, only the frames are
+// read and written by the platform's `Connection`
+impl RichClient {
+    /// Sends the activity unless it is already shown. Past the rate limit
+    /// the packet is held back and `RateLimited` is returned.
+    pub fn update(&mut self, packet: &Packet) -> Result<(), ConnectionError> {
+        let is_unchanged = match (&packet.activity, &self.last_activity) {
+            (Some(activity), Some(last)) if self.ignore_timestamp_changes => {
+                activity.eq_ignoring_timestamp(last)
+            }
+            (activity, last) => activity == last,
+        };
+        if is_unchanged {
+            self.pending = None;
+            return Ok(());
+        }
+        // Updates past the limit would be dropped by Discord, so only the
+        // latest one is kept and written by `flush` once the interval passes
+        if !self.rate_limit.try_acquire(Instant::now()) {
+            self.pending = Some(Packet {
+                pid: packet.pid,
+                activity: packet.activity.clone(),
+            });
+            return Err(ConnectionError::RateLimited);
+        }
+
+        self.pending = None;
+        self.write(1, Some(packet.to_json().unwrap().as_bytes()))?;
+        self.last_activity = packet.activity.clone();
+        self.is_cleared = packet.activity.is_none();
+
+        if self.check_responses {
+            let frame = self.read()?;
+            utils::validate_response(&frame)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the update held back by the rate limit once the window allows.
+    pub fn flush(&mut self) -> Result<(), ConnectionError> {
+        match self.pending.take() {
+            Some(packet) => self.update(&packet),
+            None => Ok(()),
+        }
+    }
+
+    /// Clears the activity and forgets the last one, so the next `update`
+    /// sends it again even if it did not change in the meantime.
+    pub fn clear(&mut self) -> Result<(), ConnectionError> {
+        self.pending = None;
+        // Discord already shows nothing, another empty frame would be wasted
+        if self.is_cleared {
+            return Ok(());
+        }
+
+        self.last_activity = None;
+        self.write(
+            1,
+            Some(
+                Packet {
+                    pid: std::process::id(),
+                    activity: None,
+                }
+                .to_json()
+                .unwrap()
+                .as_bytes(),
+            ),
+        )?;
+        self.is_cleared = true;
+
+        // Read even though nothing is shown, otherwise the next update would
+        // validate this reply instead of its own
+        if self.check_responses {
+            let frame = self.read()?;
+            utils::validate_response(&frame)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limit_allows_one_write_per_interval() {
+        let start = Instant::now();
+        let mut limit = RateLimit::default();
+
+        assert!(limit.try_acquire(start));
+        assert!(!limit.try_acquire(start));
+        assert!(!limit.try_acquire(start + Duration::from_secs(14)));
+        assert!(limit.try_acquire(start + RATE_LIMIT_INTERVAL));
+        assert!(!limit.try_acquire(start + Duration::from_secs(20)));
+    }
 }
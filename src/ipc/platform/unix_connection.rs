@@ -1,109 +1,567 @@
 use std::env::var;
 use std::io::{self, Read, Write};
 use std::os::unix::net::UnixStream;
+use std::sync::Mutex;
+use std::time::Duration;
 
-use crate::ipc::client::{Connection, RichClient};
+use crate::ipc::client::{
+    Connection, ConnectionError, Frame, Opcode, RateLimit, RichClient,
+    DISCORD_BUILD, READ_TIMEOUT,
+};
 use crate::ipc::utils;
-use crate::rpc::packet::Packet;
+
+static LAST_SOCKET: Mutex<Option<String>> = Mutex::new(None);
+
+/// Subdirectories of each root that sandboxed Discord builds place their
+/// socket in, probed after the root itself.
+const SANDBOX_DIRS: [&str; 3] = [
+    "app/com.discordapp.Discord",
+    "snap.discord",
+    "app/com.discordapp.DiscordCanary",
+];
+
+/// Directories that may contain the Discord socket, in search order.
+/// Environment variables take priority over the compiled-in defaults.
+fn socket_roots() -> Vec<String> {
+    let mut roots: Vec<String> = ["XDG_RUNTIME_DIR", "TMPDIR", "TMP", "TEMP"]
+        .iter()
+        .filter_map(|name| var(name).ok())
+        .filter(|root| !root.is_empty())
+        .map(|root| root.trim_end_matches('/').to_string())
+        .collect();
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        if let Ok(metadata) = std::fs::metadata("/proc/self") {
+            roots.push(format!("/run/user/{}", metadata.uid()));
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    roots.push("/private/tmp".to_string());
+
+    roots.push("/tmp".to_string());
+
+    let mut unique = Vec::with_capacity(roots.len());
+    for root in roots {
+        if !unique.contains(&root) {
+            unique.push(root);
+        }
+    }
+
+    unique
+}
 
 impl Connection for RichClient {
-    fn connect(client_id: u64) -> Result<Self, Box<dyn std::error::Error>> {
-        let path = var("XDG_RUNTIME_DIR")
-            .or_else(|_| var("TMPDIR"))
-            .or_else(|_| var("TMP"))
-            .or_else(|_| var("TEMP"))
-            .unwrap_or_else(|_| "/tmp".to_string());
-        for i in 0..10 {
-            match UnixStream::connect(format!("{}/discord-ipc-{}", path, i)) {
-                Ok(pipe) => {
-                    return Ok(RichClient {
-                        client_id: client_id,
-                        pipe: Some(pipe),
-                        last_activity: None,
-                    })
-                }
-                Err(e) => match e.kind() {
-                    io::ErrorKind::NotFound => continue,
-                    _ => return Err(e.into()),
-                },
-            }
+    fn connect(
+        client_id: u64,
+        attempts: u32,
+        base_delay: Duration,
+    ) -> Result<Self, ConnectionError> {
+        utils::with_backoff(attempts, base_delay, || {
+            RichClient::scan(client_id)
+        })
+    }
+
+    /// Checks for the data directories of the known Discord builds, to tell a
+    /// missing installation apart from a client that is not running.
+    fn is_installed() -> bool {
+        // Discord may live elsewhere, e.g. on the Windows side under WSL
+        if utils::ipc_path().is_some() {
+            return true;
         }
+        let home = match var("HOME") {
+            Ok(home) if !home.is_empty() => home,
+            _ => return true,
+        };
+        let config_home = var("XDG_CONFIG_HOME")
+            .unwrap_or_else(|_| format!("{}/.config", home));
 
-        Err("Pipe not found".into())
+        let dirs = [
+            format!("{}/discord", config_home),
+            format!("{}/discordcanary", config_home),
+            format!("{}/discordptb", config_home),
+            format!("{}/vesktop", config_home),
+            format!("{}/.var/app/com.discordapp.Discord", home),
+            format!("{}/.var/app/dev.vencord.Vesktop", home),
+            "/snap/discord".to_string(),
+        ];
+        #[cfg(target_os = "macos")]
+        let dirs: Vec<String> = dirs
+            .into_iter()
+            .chain([
+                format!("{}/Library/Application Support/discord", home),
+                format!("{}/Library/Application Support/discordcanary", home),
+                format!("{}/Library/Application Support/discordptb", home),
+                "/Applications/Discord.app".to_string(),
+            ])
+            .collect();
+
+        dirs.iter().any(|dir| std::path::Path::new(dir).is_dir())
     }
 
-    fn write(&mut self, opcode: u32, data: Option<&[u8]>) -> io::Result<()> {
-        if let Some(pipe) = &mut self.pipe {
-            let payload = match data {
-                Some(packet) => {
-                    let mut payload =
-                        utils::encode(opcode, packet.len() as u32);
-                    payload.extend_from_slice(packet);
-                    payload
-                }
-                None => utils::encode(opcode, 0),
-            };
-            pipe.write_all(&payload)?;
+    fn write(
+        &mut self,
+        opcode: u32,
+        data: Option<&[u8]>,
+    ) -> Result<(), ConnectionError> {
+        match self.write_frame(opcode, data) {
+            // Discord was restarted, so the stale stream is replaced once
+            // before giving up on this write
+            Err(e) if utils::is_disconnect(&e) => {
+                self.reconnect()?;
+                Ok(self.write_frame(opcode, data)?)
+            }
+            result => Ok(result?),
         }
+    }
+
+    fn reconnect(&mut self) -> Result<(), ConnectionError> {
+        self.pipe = None;
+        self.last_activity = None;
+        self.is_cleared = false;
+
+        self.pipe = RichClient::connect(self.client_id, 1, Duration::ZERO)?
+            .pipe
+            .take();
 
         Ok(())
     }
 
-    fn read(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        self.pipe
-            .as_mut()
-            .map_or(Err("Pipe not found".into()), |pipe| {
-                let mut header = [0; 8];
-                pipe.read_exact(&mut header)?;
-                let size = utils::decode(&header) as usize;
-                let mut buffer = vec![0u8; size];
-                pipe.read_exact(&mut buffer)?;
-                Ok(buffer)
-            })
+    fn read(&mut self) -> Result<Frame, ConnectionError> {
+        loop {
+            let pipe = self.pipe.as_mut().ok_or(ConnectionError::NotFound)?;
+            let mut header = [0; 8];
+            if let Err(e) = pipe.read_exact(&mut header) {
+                return Err(self.read_failed(e));
+            }
+            let opcode = Opcode::from_u32(utils::decode_opcode(&header));
+            let size = match utils::frame_size(&header) {
+                Ok(size) => size,
+                Err(e) => return Err(self.read_failed(e)),
+            };
+            let mut payload = vec![0u8; size];
+            if let Err(e) = pipe.read_exact(&mut payload) {
+                return Err(self.read_failed(e));
+            }
+
+            match opcode {
+                // Frames with opcodes we don't know about are consumed whole
+                // and ignored, so newer Discord versions don't break reading
+                Opcode::Unknown(_) => continue,
+                // Discord is done with this connection, dropping the stream
+                // makes the next write reconnect
+                Opcode::Close => self.pipe = None,
+                _ => {}
+            }
+
+            return Ok(Frame { opcode, payload });
+        }
     }
 
-    fn close(&mut self) -> io::Result<()> {
-        if let Some(mut pipe) = self.pipe.take() {
-            pipe.write_all(&utils::encode(2, 0))?;
-            pipe.shutdown(std::net::Shutdown::Both)?;
+    fn close(&mut self) -> Result<(), ConnectionError> {
+        if self.pipe.is_none() {
+            return Ok(());
+        }
+
+        // Best effort, Discord may already be gone, in which case neither
+        // the CLOSE frame nor the shutdown can succeed. The stream is
+        // dropped either way
+        let _ = self.write_frame(2, None);
+        if let Some(pipe) = self.pipe.take() {
+            let _ = pipe.shutdown(std::net::Shutdown::Both);
         }
 
         Ok(())
     }
 
-    fn handshake(&mut self) -> io::Result<()> {
-        self.write(
+    fn handshake(&mut self) -> Result<(), ConnectionError> {
+        // Written directly, a failing handshake must not start a reconnect
+        self.write_frame(
             0,
             Some(
                 (format!("{{\"v\": 1,\"client_id\":\"{}\"}}", self.client_id))
                     .as_bytes(),
             ),
-        )
+        )?;
+
+        let frame = self.read()?;
+        let build = DISCORD_BUILD.lock().ok().and_then(|build| build.clone());
+        utils::validate_ready(&frame, build.as_deref())
+    }
+}
+
+impl RichClient {
+    /// A single pass over every socket Discord may be listening on. A missing
+    /// socket or one that refuses the handshake moves on to the next index,
+    /// any other error ends the pass.
+    fn scan(client_id: u64) -> Result<Self, ConnectionError> {
+        let mut result = Err(ConnectionError::NotFound);
+        // An explicit path, e.g. a socket bridged from Windows under WSL, is
+        // tried before anything else
+        if let Some(socket) = utils::ipc_path() {
+            if let Ok(pipe) = UnixStream::connect(&socket) {
+                match RichClient::greet(client_id, pipe) {
+                    Ok(client) => return Ok(client),
+                    Err(e) => result = Err(e),
+                }
+            }
+        }
+
+        // Discord may come back on a different index after a restart, so the
+        // previous socket is only tried first before scanning all of them
+        let last_socket = LAST_SOCKET.lock().ok().and_then(|last| last.clone());
+        if let Some(socket) = last_socket {
+            if let Ok(pipe) = UnixStream::connect(&socket) {
+                match RichClient::greet(client_id, pipe) {
+                    Ok(client) => return Ok(client),
+                    Err(e) => result = Err(e),
+                }
+            }
+        }
+
+        let dirs = socket_roots().into_iter().flat_map(|root| {
+            let sandboxed = SANDBOX_DIRS
+                .iter()
+                .map(|dir| format!("{}/{}", root, dir))
+                .collect::<Vec<_>>();
+            std::iter::once(root).chain(sandboxed)
+        });
+
+        for path in dirs {
+            for i in 0..10 {
+                let socket = format!("{}/discord-ipc-{}", path, i);
+                match UnixStream::connect(&socket) {
+                    Ok(pipe) => match RichClient::greet(client_id, pipe) {
+                        Ok(client) => {
+                            if let Ok(mut last) = LAST_SOCKET.lock() {
+                                *last = Some(socket);
+                            }
+                            return Ok(client);
+                        }
+                        // Another build or another program answered, a later index
+                        // may still be the right one
+                        Err(e) => result = Err(e),
+                    },
+                    Err(e) => match e.kind() {
+                        io::ErrorKind::NotFound => continue,
+                        _ => return Err(e.into()),
+                    },
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Performs the handshake on a freshly opened connection.
+    fn greet(
+        client_id: u64,
+        pipe: UnixStream,
+    ) -> Result<Self, ConnectionError> {
+        let mut client = RichClient::with_pipe(client_id, pipe);
+        client.handshake()?;
+
+        Ok(client)
+    }
+
+    fn with_pipe(client_id: u64, pipe: UnixStream) -> Self {
+        // Without a timeout a stalled Discord would block reads forever
+        let _ = pipe.set_read_timeout(Some(READ_TIMEOUT));
+
+        RichClient {
+            client_id,
+            pipe: Some(pipe),
+            last_activity: None,
+            is_cleared: false,
+            rate_limit: RateLimit::default(),
+            pending: None,
+            ignore_timestamp_changes: false,
+            check_responses: false,
+        }
+    }
+
+    /// A stream that ends before a whole frame was read has been closed by
+    /// Discord, and one with an oversized header or a reply that timed out is
+    /// out of sync. Neither can be read from again, dropping it makes the
+    /// next write reconnect.
+    fn read_failed(&mut self, error: io::Error) -> ConnectionError {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                self.pipe = None;
+                ConnectionError::Closed
+            }
+            io::ErrorKind::InvalidData
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock => {
+                self.pipe = None;
+                error.into()
+            }
+            _ => error.into(),
+        }
     }
 
-    fn update(
+    fn write_frame(
         &mut self,
-        packet: &crate::rpc::packet::Packet,
+        opcode: u32,
+        data: Option<&[u8]>,
     ) -> io::Result<()> {
-        if packet.activity != self.last_activity {
-            return self.write(1, Some(packet.to_json().unwrap().as_bytes()));
+        let pipe = match &mut self.pipe {
+            Some(pipe) => pipe,
+            // Left unset by a reconnect that failed
+            None => return Err(io::ErrorKind::BrokenPipe.into()),
+        };
+        let payload = match data {
+            Some(packet) => {
+                let mut payload = utils::encode(opcode, packet.len() as u32);
+                payload.extend_from_slice(packet);
+                payload
+            }
+            None => utils::encode(opcode, 0),
+        };
+
+        pipe.write_all(&payload)?;
+        pipe.flush()
+    }
+}
+
+impl Drop for RichClient {
+    /// Closes the connection when the client goes away without an explicit
+    /// `close`, e.g. when Neovim exits, so Discord drops the presence right
+    /// away. After `close` the pipe is already taken and nothing is sent.
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::activity::Activity;
+    use crate::rpc::packet::Packet;
+    use std::os::unix::net::UnixListener;
+
+    /// Serializes the tests that point `XDG_RUNTIME_DIR` at their own socket.
+    static RUNTIME_DIR: Mutex<()> = Mutex::new(());
+
+    /// Creates an empty directory and makes it the first one scanned.
+    fn runtime_dir(name: &str) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "cord-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::env::set_var("XDG_RUNTIME_DIR", &root);
+
+        root
+    }
+
+    fn read_frame(stream: &mut UnixStream) -> (u32, Vec<u8>) {
+        let mut header = [0; 8];
+        stream.read_exact(&mut header).unwrap();
+        let mut payload = vec![0; utils::decode(&header) as usize];
+        stream.read_exact(&mut payload).unwrap();
+
+        (utils::decode_opcode(&header), payload)
+    }
+
+    fn write_frame(stream: &mut UnixStream, opcode: u32, payload: &str) {
+        let mut frame = utils::encode(opcode, payload.len() as u32);
+        frame.extend_from_slice(payload.as_bytes());
+        stream.write_all(&frame).unwrap();
+    }
+
+    /// Accepts a single client and answers its handshake like Discord.
+    fn answer_handshake(listener: &UnixListener) -> UnixStream {
+        let (mut stream, _) = listener.accept().unwrap();
+        let (opcode, _) = read_frame(&mut stream);
+        assert_eq!(opcode, 0);
+        write_frame(&mut stream, 1, r#"{"evt":"READY","data":{}}"#);
+
+        stream
+    }
+
+    #[test]
+    fn discord_is_only_installed_with_a_data_directory() {
+        let _runtime_dir =
+            RUNTIME_DIR.lock().unwrap_or_else(|e| e.into_inner());
+        let home = runtime_dir("home");
+        let saved = (var("HOME").ok(), var("XDG_CONFIG_HOME").ok());
+        std::env::set_var("HOME", &home);
+        std::env::remove_var("XDG_CONFIG_HOME");
+
+        // A system-wide snap counts as installed for every user
+        if !std::path::Path::new("/snap/discord").is_dir() {
+            assert!(!RichClient::is_installed());
         }
+        std::fs::create_dir_all(home.join(".config").join("discord")).unwrap();
+        assert!(RichClient::is_installed());
 
-        Ok(())
+        for (name, value) in [("HOME", saved.0), ("XDG_CONFIG_HOME", saved.1)] {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn env_roots_come_before_the_compiled_in_defaults() {
+        let _runtime_dir =
+            RUNTIME_DIR.lock().unwrap_or_else(|e| e.into_inner());
+        let root = runtime_dir("roots");
+        let roots = socket_roots();
+
+        assert_eq!(roots[0], root.to_string_lossy());
+        assert_eq!(roots.last().map(String::as_str), Some("/tmp"));
+        assert_eq!(roots.iter().filter(|root| *root == "/tmp").count(), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn linux_roots_include_the_user_runtime_dir() {
+        use std::os::unix::fs::MetadataExt;
+
+        let _runtime_dir =
+            RUNTIME_DIR.lock().unwrap_or_else(|e| e.into_inner());
+        let root = runtime_dir("linux-roots");
+        let uid = std::fs::metadata(&root).unwrap().uid();
+
+        assert!(socket_roots().contains(&format!("/run/user/{}", uid)));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn macos_roots_include_the_private_tmp_dir() {
+        let _runtime_dir =
+            RUNTIME_DIR.lock().unwrap_or_else(|e| e.into_inner());
+
+        assert!(socket_roots().contains(&"/private/tmp".to_string()));
+    }
+
+    #[test]
+    fn reconnect_scans_past_a_socket_that_is_gone() {
+        let _runtime_dir =
+            RUNTIME_DIR.lock().unwrap_or_else(|e| e.into_inner());
+        let root = runtime_dir("ipc");
+
+        // Discord came back on index 3 after listening on index 0
+        let old_socket = root.join("discord-ipc-0");
+        let new_socket = root.join("discord-ipc-3");
+        *LAST_SOCKET.lock().unwrap() =
+            Some(old_socket.to_string_lossy().to_string());
+        let listener = UnixListener::bind(&new_socket).unwrap();
+        let discord = std::thread::spawn(move || answer_handshake(&listener));
+
+        let (stale, _) = UnixStream::pair().unwrap();
+        let mut client = RichClient::with_pipe(1, stale);
+        client.reconnect().unwrap();
+        let _discord = discord.join().unwrap();
+
+        assert!(client.pipe.is_some());
+        assert_eq!(
+            LAST_SOCKET.lock().unwrap().as_deref(),
+            Some(new_socket.to_string_lossy().as_ref())
+        );
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
-    fn clear(&mut self) -> io::Result<()> {
-        self.write(
+    #[test]
+    fn resends_after_rapid_reconnects_stay_rate_limited() {
+        let _runtime_dir =
+            RUNTIME_DIR.lock().unwrap_or_else(|e| e.into_inner());
+        let root = runtime_dir("flapping");
+        let listener = std::sync::Arc::new(
+            UnixListener::bind(root.join("discord-ipc-0")).unwrap(),
+        );
+        let packet = Packet::new(
             1,
-            Some(
-                Packet {
-                    pid: std::process::id(),
-                    activity: None,
-                }
-                .to_json()
-                .unwrap()
-                .as_bytes(),
-            ),
-        )
+            Some(Activity {
+                details: Some("Editing main.rs".to_string()),
+                ..Default::default()
+            }),
+        );
+
+        // The first connection sends right away
+        let (pipe, mut discord) = UnixStream::pair().unwrap();
+        let mut client = RichClient::with_pipe(1, pipe);
+        client.update(&packet).unwrap();
+        let (_, payload) = read_frame(&mut discord);
+        assert!(String::from_utf8(payload)
+            .unwrap()
+            .contains("Editing main.rs"));
+
+        for _ in 0..3 {
+            drop(discord);
+            let accept = {
+                let listener = listener.clone();
+                std::thread::spawn(move || answer_handshake(&listener))
+            };
+            client.reconnect().unwrap();
+            discord = accept.join().unwrap();
+
+            // The reconnect forgot the activity, but sending it again has to
+            // wait for the interval like any other update
+            assert!(client.last_activity.is_none());
+            assert!(matches!(
+                client.update(&packet),
+                Err(ConnectionError::RateLimited)
+            ));
+            assert!(client.pending.is_some());
+            discord.set_nonblocking(true).unwrap();
+            assert_eq!(
+                discord.read(&mut [0; 8]).unwrap_err().kind(),
+                io::ErrorKind::WouldBlock
+            );
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn repeated_clears_send_a_single_frame() {
+        let (pipe, mut discord) = UnixStream::pair().unwrap();
+        let mut client = RichClient::with_pipe(1, pipe);
+
+        client.clear().unwrap();
+        client.clear().unwrap();
+        client.close().unwrap();
+
+        let (opcode, payload) = read_frame(&mut discord);
+        assert_eq!(opcode, 1);
+        assert!(String::from_utf8(payload).unwrap().contains("SET_ACTIVITY"));
+        // The next frame is the goodbye, not a second clear
+        assert_eq!(read_frame(&mut discord).0, 2);
+    }
+
+    #[test]
+    fn frames_with_unknown_opcodes_are_skipped() {
+        let (pipe, mut discord) = UnixStream::pair().unwrap();
+        let mut client = RichClient::with_pipe(1, pipe);
+
+        write_frame(&mut discord, 7, r#"{"evt":"FROM_THE_FUTURE"}"#);
+        write_frame(&mut discord, 1, r#"{"evt":null}"#);
+        let frame = client.read().unwrap();
+
+        assert_eq!(frame.opcode, Opcode::Frame);
+        assert_eq!(frame.payload, br#"{"evt":null}"#);
+        assert!(client.pipe.is_some());
+    }
+
+    #[test]
+    fn an_oversized_frame_drops_the_connection() {
+        let (pipe, mut discord) = UnixStream::pair().unwrap();
+        let mut client = RichClient::with_pipe(1, pipe);
+
+        discord.write_all(&utils::encode(1, u32::MAX)).unwrap();
+
+        assert!(client.read().is_err());
+        assert!(client.pipe.is_none());
     }
 }
@@ -1,108 +1,317 @@
+use std::ffi::c_void;
 use std::fs::OpenOptions;
 use std::io::{self, Read, Write};
 use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::AsRawHandle;
+use std::ptr::null_mut;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use crate::ipc::client::{Connection, RichClient};
+use crate::ipc::client::{
+    Connection, ConnectionError, Frame, Opcode, RateLimit, RichClient,
+    DISCORD_BUILD, READ_TIMEOUT,
+};
 use crate::ipc::utils;
-use crate::rpc::packet::Packet;
 
-impl Connection for RichClient {
-    fn connect(client_id: u64) -> Result<Self, Box<dyn std::error::Error>> {
-        for i in 0..10 {
-            match OpenOptions::new()
-                .read(true)
-                .write(true)
-                .access_mode(0x3)
-                .open(format!("\\\\.\\pipe\\discord-ipc-{}", i))
-            {
-                Ok(pipe) => {
-                    return Ok(RichClient {
-                        client_id: client_id,
-                        pipe: Some(pipe),
-                        last_activity: None,
-                    })
-                }
-                Err(e) => match e.kind() {
-                    io::ErrorKind::NotFound => continue,
-                    _ => return Err(e.into()),
-                },
-            }
+static LAST_PIPE: Mutex<Option<String>> = Mutex::new(None);
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn PeekNamedPipe(
+        pipe: *mut c_void,
+        buffer: *mut c_void,
+        buffer_size: u32,
+        bytes_read: *mut u32,
+        bytes_available: *mut u32,
+        bytes_left: *mut u32,
+    ) -> i32;
+}
+
+/// Named pipes opened through `std` have no read timeout, so the pipe is
+/// polled until data arrives or `timeout` passes.
+fn wait_readable(pipe: &std::fs::File, timeout: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let mut available = 0;
+        let result = unsafe {
+            PeekNamedPipe(
+                pipe.as_raw_handle() as *mut c_void,
+                null_mut(),
+                0,
+                null_mut(),
+                &mut available,
+                null_mut(),
+            )
+        };
+        if result == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if available > 0 {
+            return Ok(());
         }
+        if Instant::now() >= deadline {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[inline(always)]
+fn open_pipe(path: &str) -> io::Result<std::fs::File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .access_mode(0x3)
+        .open(path)
+}
 
-        Err("Pipe not found".into())
+impl Connection for RichClient {
+    fn connect(
+        client_id: u64,
+        attempts: u32,
+        base_delay: Duration,
+    ) -> Result<Self, ConnectionError> {
+        utils::with_backoff(attempts, base_delay, || {
+            RichClient::scan(client_id)
+        })
     }
 
-    fn write(&mut self, opcode: u32, data: Option<&[u8]>) -> io::Result<()> {
-        if let Some(pipe) = &mut self.pipe {
-            let payload = match data {
-                Some(packet) => {
-                    let mut payload =
-                        utils::encode(opcode, packet.len() as u32);
-                    payload.extend_from_slice(packet);
-                    payload
-                }
-                None => utils::encode(opcode, 0),
-            };
-            pipe.write_all(&payload)?;
+    /// Checks for the data directories of the known Discord builds, to tell a
+    /// missing installation apart from a client that is not running.
+    fn is_installed() -> bool {
+        let app_data = match std::env::var("APPDATA") {
+            Ok(app_data) if !app_data.is_empty() => app_data,
+            _ => return true,
+        };
+        let local_app_data = std::env::var("LOCALAPPDATA").unwrap_or_default();
+
+        [
+            format!("{}\\discord", app_data),
+            format!("{}\\discordcanary", app_data),
+            format!("{}\\discordptb", app_data),
+            format!("{}\\vesktop", app_data),
+            format!("{}\\Discord", local_app_data),
+        ]
+        .iter()
+        .any(|dir| std::path::Path::new(dir).is_dir())
+    }
+
+    fn write(
+        &mut self,
+        opcode: u32,
+        data: Option<&[u8]>,
+    ) -> Result<(), ConnectionError> {
+        match self.write_frame(opcode, data) {
+            // Discord was restarted, so the stale stream is replaced once
+            // before giving up on this write
+            Err(e) if utils::is_disconnect(&e) => {
+                self.reconnect()?;
+                Ok(self.write_frame(opcode, data)?)
+            }
+            result => Ok(result?),
         }
+    }
+
+    fn reconnect(&mut self) -> Result<(), ConnectionError> {
+        self.pipe = None;
+        self.last_activity = None;
+        self.is_cleared = false;
+
+        self.pipe = RichClient::connect(self.client_id, 1, Duration::ZERO)?
+            .pipe
+            .take();
 
         Ok(())
     }
 
-    fn read(&mut self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        self.pipe
-            .as_mut()
-            .map_or(Err("Pipe not found".into()), |pipe| {
-                let mut header = [0; 8];
-                pipe.read_exact(&mut header)?;
-                let size = utils::decode(&header) as usize;
-                let mut buffer = vec![0u8; size];
-                pipe.read_exact(&mut buffer)?;
-                Ok(buffer)
-            })
+    fn read(&mut self) -> Result<Frame, ConnectionError> {
+        loop {
+            let pipe = self.pipe.as_mut().ok_or(ConnectionError::NotFound)?;
+            let mut header = [0; 8];
+            if let Err(e) = wait_readable(pipe, READ_TIMEOUT)
+                .and_then(|_| pipe.read_exact(&mut header))
+            {
+                return Err(self.read_failed(e));
+            }
+            let opcode = Opcode::from_u32(utils::decode_opcode(&header));
+            let size = match utils::frame_size(&header) {
+                Ok(size) => size,
+                Err(e) => return Err(self.read_failed(e)),
+            };
+            let mut payload = vec![0u8; size];
+            if let Err(e) = pipe.read_exact(&mut payload) {
+                return Err(self.read_failed(e));
+            }
+
+            match opcode {
+                // Frames with opcodes we don't know about are consumed whole
+                // and ignored, so newer Discord versions don't break reading
+                Opcode::Unknown(_) => continue,
+                // Discord is done with this connection, dropping the stream
+                // makes the next write reconnect
+                Opcode::Close => self.pipe = None,
+                _ => {}
+            }
+
+            return Ok(Frame { opcode, payload });
+        }
     }
 
-    fn close(&mut self) -> io::Result<()> {
-        if let Some(mut pipe) = self.pipe.take() {
-            pipe.write_all(&utils::encode(2, 0))?;
+    fn close(&mut self) -> Result<(), ConnectionError> {
+        if self.pipe.is_none() {
+            return Ok(());
         }
 
+        // Best effort, Discord may already be gone, in which case the CLOSE
+        // frame cannot be written. The pipe is dropped either way
+        let _ = self.write_frame(2, None);
+        self.pipe = None;
+
         Ok(())
     }
 
-    fn handshake(&mut self) -> io::Result<()> {
-        self.write(
+    fn handshake(&mut self) -> Result<(), ConnectionError> {
+        // Written directly, a failing handshake must not start a reconnect
+        self.write_frame(
             0,
             Some(
                 format!("{{\"v\": 1,\"client_id\":\"{}\"}}", self.client_id)
                     .as_bytes(),
             ),
-        )
+        )?;
+
+        let frame = self.read()?;
+        let build = DISCORD_BUILD.lock().ok().and_then(|build| build.clone());
+        utils::validate_ready(&frame, build.as_deref())
+    }
+}
+
+impl RichClient {
+    /// A single pass over every pipe Discord may be listening on. A missing
+    /// pipe or one that refuses the handshake moves on to the next index,
+    /// any other error ends the pass.
+    fn scan(client_id: u64) -> Result<Self, ConnectionError> {
+        let mut result = Err(ConnectionError::NotFound);
+        // An explicit path, e.g. a socket bridged from Windows under WSL, is
+        // tried before anything else
+        if let Some(path) = utils::ipc_path() {
+            if let Ok(pipe) = open_pipe(&path) {
+                match RichClient::greet(client_id, pipe) {
+                    Ok(client) => return Ok(client),
+                    Err(e) => result = Err(e),
+                }
+            }
+        }
+
+        // Discord may come back on a different index after a restart, so the
+        // previous pipe is only tried first before scanning all of them
+        let last_pipe = LAST_PIPE.lock().ok().and_then(|last| last.clone());
+        if let Some(path) = last_pipe {
+            if let Ok(pipe) = open_pipe(&path) {
+                match RichClient::greet(client_id, pipe) {
+                    Ok(client) => return Ok(client),
+                    Err(e) => result = Err(e),
+                }
+            }
+        }
+
+        for i in 0..10 {
+            let path = format!("\\\\.\\pipe\\discord-ipc-{}", i);
+            match open_pipe(&path) {
+                Ok(pipe) => match RichClient::greet(client_id, pipe) {
+                    Ok(client) => {
+                        if let Ok(mut last) = LAST_PIPE.lock() {
+                            *last = Some(path);
+                        }
+                        return Ok(client);
+                    }
+                    // Another build or another program answered, a later index
+                    // may still be the right one
+                    Err(e) => result = Err(e),
+                },
+                Err(e) => match e.kind() {
+                    io::ErrorKind::NotFound => continue,
+                    _ => return Err(e.into()),
+                },
+            }
+        }
+
+        result
+    }
+
+    /// Performs the handshake on a freshly opened connection.
+    fn greet(
+        client_id: u64,
+        pipe: std::fs::File,
+    ) -> Result<Self, ConnectionError> {
+        let mut client = RichClient::with_pipe(client_id, pipe);
+        client.handshake()?;
+
+        Ok(client)
+    }
+
+    fn with_pipe(client_id: u64, pipe: std::fs::File) -> Self {
+        RichClient {
+            client_id,
+            pipe: Some(pipe),
+            last_activity: None,
+            is_cleared: false,
+            rate_limit: RateLimit::default(),
+            pending: None,
+            ignore_timestamp_changes: false,
+            check_responses: false,
+        }
     }
 
-    fn update(
+    /// A stream that ends before a whole frame was read has been closed by
+    /// Discord, and one with an oversized header or a reply that timed out is
+    /// out of sync. Neither can be read from again, dropping it makes the
+    /// next write reconnect.
+    fn read_failed(&mut self, error: io::Error) -> ConnectionError {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => {
+                self.pipe = None;
+                ConnectionError::Closed
+            }
+            io::ErrorKind::InvalidData
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock => {
+                self.pipe = None;
+                error.into()
+            }
+            _ => error.into(),
+        }
+    }
+
+    fn write_frame(
         &mut self,
-        packet: &crate::rpc::packet::Packet,
+        opcode: u32,
+        data: Option<&[u8]>,
     ) -> io::Result<()> {
-        if packet.activity != self.last_activity {
-            return self.write(1, Some(packet.to_json().unwrap().as_bytes()));
-        }
+        let pipe = match &mut self.pipe {
+            Some(pipe) => pipe,
+            // Left unset by a reconnect that failed
+            None => return Err(io::ErrorKind::BrokenPipe.into()),
+        };
+        let payload = match data {
+            Some(packet) => {
+                let mut payload = utils::encode(opcode, packet.len() as u32);
+                payload.extend_from_slice(packet);
+                payload
+            }
+            None => utils::encode(opcode, 0),
+        };
 
-        Ok(())
+        pipe.write_all(&payload)?;
+        pipe.flush()
     }
+}
 
-    fn clear(&mut self) -> io::Result<()> {
-        self.write(
-            1,
-            Some(
-                Packet {
-                    pid: std::process::id(),
-                    activity: None,
-                }
-                .to_json()
-                .unwrap()
-                .as_bytes(),
-            ),
-        )
+impl Drop for RichClient {
+    /// Closes the connection when the client goes away without an explicit
+    /// `close`, e.g. when Neovim exits, so Discord drops the presence right
+    /// away. After `close` the pipe is already taken and nothing is sent.
+    fn drop(&mut self) {
+        let _ = self.close();
     }
 }
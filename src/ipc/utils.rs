@@ -1,9 +1,195 @@
 use std::convert::TryInto;
+use std::io;
+use std::time::Duration;
+
+use crate::ipc::client::{ConnectionError, Frame, Opcode, IPC_PATH};
+use crate::json::deserialize::Value;
 
 pub fn encode(opcode: u32, data_length: u32) -> Vec<u8> {
     [opcode.to_le_bytes(), data_length.to_le_bytes()].concat()
 }
 
+pub fn decode_opcode(data: &[u8]) -> u32 {
+    u32::from_le_bytes(data[0..4].try_into().unwrap())
+}
+
 pub fn decode(data: &[u8]) -> u32 {
     u32::from_le_bytes(data[4..8].try_into().unwrap())
 }
+
+/// Discord's payloads are a few kilobytes at most, a header announcing more
+/// than this means the stream is corrupted.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// The payload size announced by a frame header, refused past
+/// `MAX_FRAME_SIZE` before anything is allocated for it.
+pub fn frame_size(header: &[u8]) -> io::Result<usize> {
+    let size = decode(header) as usize;
+    if size > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Frame of {} bytes exceeds the limit of {} bytes",
+                size, MAX_FRAME_SIZE
+            ),
+        ));
+    }
+
+    Ok(size)
+}
+
+/// The explicitly configured socket or pipe path. The config takes priority
+/// over the `CORD_IPC_PATH` environment variable.
+pub fn ipc_path() -> Option<String> {
+    IPC_PATH
+        .lock()
+        .ok()
+        .and_then(|path| path.clone())
+        .or_else(|| std::env::var("CORD_IPC_PATH").ok())
+        .filter(|path| !path.is_empty())
+}
+
+/// Whether a write failed because Discord closed its end of the connection.
+pub fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// Checks that Discord answered the handshake with `READY`, turning anything
+/// else into an error that carries Discord's code and message. A `build`
+/// other than the one that answered is refused as well.
+pub fn validate_ready(
+    frame: &Frame,
+    build: Option<&str>,
+) -> Result<(), ConnectionError> {
+    let value = std::str::from_utf8(&frame.payload)
+        .ok()
+        .and_then(Value::parse)
+        .ok_or_else(|| ConnectionError::Handshake {
+            code: None,
+            message: "Malformed handshake response".to_string(),
+        })?;
+
+    if frame.opcode == Opcode::Frame
+        && value.get("evt").and_then(Value::as_str) == Some("READY")
+    {
+        let found = ready_build(&value);
+        return match build {
+            Some(build) if build != found => Err(ConnectionError::Handshake {
+                code: None,
+                message: format!(
+                    "Found Discord {} instead of {}",
+                    found, build
+                ),
+            }),
+            _ => Ok(()),
+        };
+    }
+
+    let (code, message) = error_details(&value, "Handshake rejected");
+    Err(ConnectionError::Handshake { code, message })
+}
+
+/// Checks Discord's reply to a command, turning an ERROR event into an error
+/// that carries Discord's code and message.
+pub fn validate_response(frame: &Frame) -> Result<(), ConnectionError> {
+    let value = match std::str::from_utf8(&frame.payload)
+        .ok()
+        .and_then(Value::parse)
+    {
+        Some(value) => value,
+        None => {
+            return Err(ConnectionError::Rejected {
+                code: None,
+                message: "Malformed response".to_string(),
+            })
+        }
+    };
+
+    if value.get("evt").and_then(Value::as_str) != Some("ERROR") {
+        return Ok(());
+    }
+
+    let (code, message) = error_details(&value, "Activity rejected");
+    Err(ConnectionError::Rejected { code, message })
+}
+
+/// Tells the Discord builds apart by the API endpoint they report.
+#[inline(always)]
+fn ready_build(value: &Value) -> &'static str {
+    let endpoint = value
+        .get("data")
+        .and_then(|data| data.get("config"))
+        .and_then(|config| config.get("api_endpoint"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+
+    if endpoint.contains("canary.") {
+        "canary"
+    } else if endpoint.contains("ptb.") {
+        "ptb"
+    } else {
+        "stable"
+    }
+}
+
+#[inline(always)]
+fn error_details(value: &Value, fallback: &str) -> (Option<i64>, String) {
+    // ERROR events nest the details in `data`, CLOSE frames do not
+    let details = value.get("data").unwrap_or(value);
+    (
+        details
+            .get("code")
+            .and_then(Value::as_number)
+            .and_then(|code| code.parse().ok()),
+        details
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or(fallback)
+            .to_string(),
+    )
+}
+
+/// Runs `attempt` up to `attempts` times, doubling the delay after every
+/// failed try, and returns the last result.
+pub fn with_backoff<T, E>(
+    attempts: u32,
+    base_delay: Duration,
+    mut attempt: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut result = attempt();
+    let mut delay = base_delay;
+
+    for _ in 1..attempts {
+        if result.is_ok() {
+            break;
+        }
+        std::thread::sleep(delay);
+        delay = delay.saturating_mul(2);
+        result = attempt();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_sizes_up_to_the_limit_are_accepted() {
+        assert_eq!(frame_size(&encode(1, 0)).unwrap(), 0);
+        let size = MAX_FRAME_SIZE as u32;
+        assert_eq!(frame_size(&encode(1, size)).unwrap(), MAX_FRAME_SIZE);
+    }
+
+    #[test]
+    fn oversized_frame_headers_are_refused() {
+        for size in [MAX_FRAME_SIZE as u32 + 1, u32::MAX] {
+            let error = frame_size(&encode(1, size)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use crate::rpc::activity::{
+    Activity, ActivityAssets, ActivityButton, ActivityType,
+};
+
+pub enum Value {
+    Null,
+    /// Nothing reads a boolean's value yet, they are only skipped over.
+    Bool,
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+/// How deeply arrays and objects may nest. Values are parsed recursively, a
+/// reply nested deeper than any Discord sends would otherwise be able to
+/// overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+impl Value {
+    pub fn parse(json: &str) -> Option<Value> {
+        let mut parser = Parser {
+            bytes: json.as_bytes(),
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+
+        (parser.pos == parser.bytes.len()).then_some(value)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(entries) => entries
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<&str> {
+        match self {
+            Value::Number(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn consume(&mut self, expected: u8) -> Option<()> {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&expected) {
+            self.pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn consume_literal(&mut self, literal: &str) -> Option<()> {
+        if self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn parse_value(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos)? {
+            b'n' => self.consume_literal("null").map(|_| Value::Null),
+            b't' => self.consume_literal("true").map(|_| Value::Bool),
+            b'f' => self.consume_literal("false").map(|_| Value::Bool),
+            b'"' => self.parse_string().map(Value::String),
+            b'[' | b'{' if self.depth == MAX_DEPTH => None,
+            b'[' => self.nested(Parser::parse_array),
+            b'{' => self.nested(Parser::parse_object),
+            b'-' | b'0'..=b'9' => self.parse_number(),
+            _ => None,
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Option<Value>,
+    ) -> Option<Value> {
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+
+        value
+    }
+
+    fn parse_number(&mut self) -> Option<Value> {
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(|byte| {
+            matches!(byte, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        }) {
+            self.pos += 1;
+        }
+
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()
+            .map(|number| Value::Number(number.to_string()))
+    }
+
+    fn parse_string(&mut self) -> Option<String> {
+        self.consume(b'"')?;
+        let mut bytes = Vec::new();
+
+        loop {
+            let byte = *self.bytes.get(self.pos)?;
+            self.pos += 1;
+
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = *self.bytes.get(self.pos)?;
+                    self.pos += 1;
+
+                    match escaped {
+                        b'"' | b'\\' | b'/' => bytes.push(escaped),
+                        b'b' => bytes.push(b'\x08'),
+                        b'f' => bytes.push(b'\x0c'),
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'u' => {
+                            let ch = self.parse_unicode_escape()?;
+                            let mut buf = [0; 4];
+                            bytes.extend_from_slice(
+                                ch.encode_utf8(&mut buf).as_bytes(),
+                            );
+                        }
+                        _ => return None,
+                    }
+                }
+                _ => bytes.push(byte),
+            }
+        }
+
+        String::from_utf8(bytes).ok()
+    }
+
+    fn parse_hex(&mut self) -> Option<u32> {
+        let hex = std::str::from_utf8(self.bytes.get(self.pos..self.pos + 4)?)
+            .ok()?;
+        self.pos += 4;
+
+        u32::from_str_radix(hex, 16).ok()
+    }
+
+    fn parse_unicode_escape(&mut self) -> Option<char> {
+        let high = self.parse_hex()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high);
+        }
+
+        // Characters outside the BMP are encoded as a surrogate pair
+        self.consume_literal("\\u")?;
+        let low = self.parse_hex()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return None;
+        }
+
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+    }
+
+    fn parse_array(&mut self) -> Option<Value> {
+        self.consume(b'[')?;
+        let mut values = Vec::new();
+
+        if self.consume(b']').is_some() {
+            return Some(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.parse_value()?);
+            if self.consume(b']').is_some() {
+                return Some(Value::Array(values));
+            }
+            self.consume(b',')?;
+        }
+    }
+
+    fn parse_object(&mut self) -> Option<Value> {
+        self.consume(b'{')?;
+        let mut entries = Vec::new();
+
+        if self.consume(b'}').is_some() {
+            return Some(Value::Object(entries));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.consume(b':')?;
+            entries.push((key, self.parse_value()?));
+            if self.consume(b'}').is_some() {
+                return Some(Value::Object(entries));
+            }
+            self.consume(b',')?;
+        }
+    }
+}
+
+impl Activity {
+    /// Parses an activity in the shape written by `push_json`. Absent fields
+    /// are left as `None`, unknown fields are ignored.
+    pub fn from_json(json: &str) -> Option<Activity> {
+        Activity::from_value(&Value::parse(json)?)
+    }
+
+    pub fn from_value(value: &Value) -> Option<Activity> {
+        let kind = match value.get("type") {
+            Some(kind) => match kind.as_number()? {
+                "0" => ActivityType::Playing,
+                "1" => ActivityType::Streaming,
+                _ => return None,
+            },
+            None => ActivityType::Playing,
+        };
+
+        let assets = match value.get("assets") {
+            Some(assets) => Some(ActivityAssets {
+                large_image: optional_string(assets, "large_image")?,
+                large_text: optional_string(assets, "large_text")?,
+                small_image: optional_string(assets, "small_image")?,
+                small_text: optional_string(assets, "small_text")?,
+            }),
+            None => None,
+        };
+
+        let buttons = match value.get("buttons") {
+            Some(Value::Array(buttons)) => Some(
+                buttons
+                    .iter()
+                    .map(|button| {
+                        Some(ActivityButton {
+                            label: button.get("label")?.as_str()?.to_string(),
+                            url: button.get("url")?.as_str()?.to_string(),
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()?,
+            ),
+            Some(_) => return None,
+            None => None,
+        };
+
+        let timestamp = optional_timestamp(value, "start")?;
+        let end_timestamp = optional_timestamp(value, "end")?;
+
+        Some(Activity {
+            kind,
+            url: optional_string(value, "url")?,
+            details: optional_string(value, "details")?,
+            state: optional_string(value, "state")?,
+            assets,
+            buttons,
+            timestamp,
+            end_timestamp,
+        })
+    }
+}
+
+/// Reads one of the `timestamps` fields, with the same `Option` nesting as
+/// `optional_string`.
+#[inline(always)]
+fn optional_timestamp(value: &Value, key: &str) -> Option<Option<u128>> {
+    match value
+        .get("timestamps")
+        .and_then(|timestamps| timestamps.get(key))
+    {
+        Some(timestamp) => Some(Some(timestamp.as_number()?.parse().ok()?)),
+        None => Some(None),
+    }
+}
+
+/// Returns `Some(None)` for an absent or null field and `None` when the field
+/// is present with the wrong type.
+#[inline(always)]
+fn optional_string(value: &Value, key: &str) -> Option<Option<String>> {
+    match value.get(key) {
+        Some(Value::String(field)) => Some(Some(field.clone())),
+        Some(Value::Null) | None => Some(None),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(activity: &Activity) -> Option<Activity> {
+        let mut json = String::new();
+        activity.push_json(&mut json).unwrap();
+
+        Activity::from_json(&json)
+    }
+
+    #[test]
+    fn round_trips_every_optional_field() {
+        let activity = Activity {
+            kind: ActivityType::Streaming,
+            url: Some("https://twitch.tv/cord".to_string()),
+            details: Some("Editing \"main.rs\"".to_string()),
+            state: Some("Ran :%s/\\s\\+$//e".to_string()),
+            assets: Some(ActivityAssets {
+                large_image: Some("rust".to_string()),
+                large_text: Some("Rust".to_string()),
+                small_image: Some("neovim".to_string()),
+                small_text: Some("The Superior Text Editor".to_string()),
+            }),
+            buttons: Some(vec![ActivityButton {
+                label: "Repository".to_string(),
+                url: "https://github.com/vyfor/cord.nvim".to_string(),
+            }]),
+            timestamp: Some(1700000000000),
+            end_timestamp: Some(1700000900000),
+        };
+
+        assert_eq!(round_trip(&activity), Some(activity));
+    }
+
+    #[test]
+    fn round_trips_absent_fields() {
+        let activity = Activity::default();
+
+        assert_eq!(round_trip(&activity), Some(activity));
+    }
+
+    #[test]
+    fn round_trips_partial_assets() {
+        let activity = Activity {
+            assets: Some(ActivityAssets {
+                large_image: Some("rust".to_string()),
+                large_text: None,
+                small_image: None,
+                small_text: Some("Neovim".to_string()),
+            }),
+            timestamp: Some(1700000000000),
+            ..Default::default()
+        };
+
+        assert_eq!(round_trip(&activity), Some(activity));
+    }
+
+    #[test]
+    fn rejects_fields_of_the_wrong_type() {
+        assert!(Activity::from_json(r#"{"type":0,"details":1}"#).is_none());
+        assert!(Activity::from_json(r#"{"type":0,"buttons":{}}"#).is_none());
+        assert!(Activity::from_json(r#"{"type":0,"details":true}"#).is_none());
+    }
+
+    #[test]
+    fn rejects_values_nested_too_deeply() {
+        let nested = |depth: usize| {
+            format!("{}{}", "[".repeat(depth), "]".repeat(depth))
+        };
+
+        assert!(Value::parse(&nested(MAX_DEPTH)).is_some());
+        assert!(Value::parse(&nested(MAX_DEPTH + 1)).is_none());
+        assert!(Value::parse(&nested(1_000_000)).is_none());
+        assert!(Activity::from_json(&format!(
+            r#"{{"details":"cord","assets":{}}}"#,
+            nested(100_000)
+        ))
+        .is_none());
+    }
+}
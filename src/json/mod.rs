@@ -1 +1,2 @@
-mod serialize;
+pub mod deserialize;
+pub mod serialize;
@@ -5,6 +5,11 @@ use std::fmt::{Error, Write};
 
 impl Packet {
     pub fn new(pid: u32, activity: Option<Activity>) -> Packet {
+        let activity = activity.map(|mut activity| {
+            activity.normalize();
+            activity
+        });
+
         Packet { pid, activity }
     }
 
@@ -28,42 +33,54 @@ impl Packet {
 }
 
 impl Activity {
+    /// Fields are always written in the same order, so equal activities
+    /// serialize to identical bytes. Every string is escaped, since file
+    /// names, commands and symbols may contain quotes or backslashes.
     pub fn push_json(&self, json_str: &mut String) -> Result<(), Error> {
-        json_str.push_str("{\"type\":0");
+        write!(json_str, "{{\"type\":{}", self.kind as u8)?;
+
+        if let Some(url) = &self.url {
+            push_entry(json_str, "url", url);
+        }
 
-        if let Some(timestamp) = &self.timestamp {
-            write!(json_str, ",\"timestamps\":{{\"start\":{}}}", timestamp)?;
+        let timestamps: Vec<String> =
+            [("start", self.timestamp), ("end", self.end_timestamp)]
+                .iter()
+                .filter_map(|(key, value)| {
+                    value.map(|value| format!("\"{}\":{}", key, value))
+                })
+                .collect();
+        if !timestamps.is_empty() {
+            write!(json_str, ",\"timestamps\":{{{}}}", timestamps.join(","))?;
         }
 
         if let Some(details) = &self.details {
-            write!(json_str, ",\"details\":\"{}\"", details)?;
+            push_entry(json_str, "details", details);
         }
 
         if let Some(state) = &self.state {
-            write!(json_str, ",\"state\":\"{}\"", state)?;
+            push_entry(json_str, "state", state);
         }
 
         if let Some(assets) = &self.assets {
             json_str.push_str(",\"assets\":{");
 
-            if let Some(large_image) = &assets.large_image {
-                write!(json_str, "\"large_image\":\"{}\",", large_image)?;
-            }
-
-            if let Some(large_text) = &assets.large_text {
-                write!(json_str, "\"large_text\":\"{}\",", large_text)?;
-            }
-
-            if let Some(small_image) = &assets.small_image {
-                write!(json_str, "\"small_image\":\"{}\",", small_image)?;
-            }
-
-            if let Some(small_text) = &assets.small_text {
-                write!(json_str, "\"small_text\":\"{}\"", small_text)?;
-            }
-
-            if json_str.ends_with(',') {
-                json_str.pop();
+            let fields = [
+                ("large_image", &assets.large_image),
+                ("large_text", &assets.large_text),
+                ("small_image", &assets.small_image),
+                ("small_text", &assets.small_text),
+            ];
+            for (key, value) in fields
+                .iter()
+                .filter_map(|(key, value)| value.as_ref().map(|v| (key, v)))
+            {
+                if !json_str.ends_with('{') {
+                    json_str.push(',');
+                }
+                push_escaped(json_str, key);
+                json_str.push(':');
+                push_escaped(json_str, value);
             }
 
             json_str.push('}');
@@ -76,11 +93,10 @@ impl Activity {
                 if index > 0 {
                     json_str.push(',');
                 }
-                write!(
-                    json_str,
-                    "{{\"label\":\"{}\",\"url\":\"{}\"}}",
-                    button.label, button.url
-                )?;
+                json_str.push_str("{\"label\":");
+                push_escaped(json_str, &button.label);
+                push_entry(json_str, "url", &button.url);
+                json_str.push('}');
             }
 
             json_str.push(']');
@@ -91,3 +107,111 @@ impl Activity {
         Ok(())
     }
 }
+
+#[inline(always)]
+pub(crate) fn push_entry(json_str: &mut String, key: &str, value: &str) {
+    json_str.push_str(&format!(",\"{}\":", key));
+    push_escaped(json_str, value);
+}
+
+#[inline(always)]
+pub(crate) fn push_escaped(json_str: &mut String, value: &str) {
+    json_str.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => json_str.push_str("\\\""),
+            '\\' => json_str.push_str("\\\\"),
+            '\n' => json_str.push_str("\\n"),
+            '\r' => json_str.push_str("\\r"),
+            '\t' => json_str.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => {
+                json_str.push_str(&format!("\\u{:04x}", ch as u32))
+            }
+            ch => json_str.push(ch),
+        }
+    }
+    json_str.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::activity::{ActivityAssets, ActivityButton, ActivityType};
+
+    #[test]
+    fn the_same_activity_serializes_to_identical_bytes() {
+        let activity = Activity {
+            kind: ActivityType::Streaming,
+            url: Some("https://twitch.tv/cord".to_string()),
+            details: Some("Editing main.rs".to_string()),
+            state: Some("Workspace: cord".to_string()),
+            assets: Some(ActivityAssets {
+                large_image: Some("rust".to_string()),
+                large_text: Some("Rust".to_string()),
+                small_image: Some("editor".to_string()),
+                small_text: Some("Neovim".to_string()),
+            }),
+            buttons: Some(vec![
+                ActivityButton {
+                    label: "Repository".to_string(),
+                    url: "https://github.com/vyfor/cord.nvim".to_string(),
+                },
+                ActivityButton {
+                    label: "Docs".to_string(),
+                    url: "https://example.com".to_string(),
+                },
+            ]),
+            timestamp: Some(1),
+            end_timestamp: Some(2),
+        };
+        let first = Packet::new(1, Some(activity.clone())).to_json().unwrap();
+        let second = Packet::new(1, Some(activity)).to_json().unwrap();
+
+        assert_eq!(first.as_bytes(), second.as_bytes());
+        assert_eq!(
+            first,
+            concat!(
+                r#"{"cmd":"SET_ACTIVITY","nonce":"-","args":{"pid":1,"#,
+                r#""activity":{"type":1,"url":"https://twitch.tv/cord","#,
+                r#""timestamps":{"start":1,"end":2},"#,
+                r#""details":"Editing main.rs","state":"Workspace: cord","#,
+                r#""assets":{"large_image":"rust","large_text":"Rust","#,
+                r#""small_image":"editor","small_text":"Neovim"},"#,
+                r#""buttons":[{"label":"Repository","#,
+                r#""url":"https://github.com/vyfor/cord.nvim"},"#,
+                r#"{"label":"Docs","url":"https://example.com"}]}}}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn escapes_quotes_and_backslashes() {
+        let activity = Activity {
+            details: Some("Editing \"quoted\".rs".to_string()),
+            state: Some("Ran :%s/\\s\\+$//e".to_string()),
+            assets: Some(ActivityAssets {
+                large_image: None,
+                large_text: Some("C:\\Users".to_string()),
+                small_image: Some("editor".to_string()),
+                small_text: None,
+            }),
+            buttons: Some(vec![ActivityButton {
+                label: "Say \"hi\"".to_string(),
+                url: "https://example.com".to_string(),
+            }]),
+            ..Default::default()
+        };
+        let mut json = String::new();
+        activity.push_json(&mut json).unwrap();
+
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"type":0,"details":"Editing \"quoted\".rs","#,
+                r#""state":"Ran :%s/\\s\\+$//e","#,
+                r#""assets":{"large_text":"C:\\Users","small_image":"editor"},"#,
+                r#""buttons":[{"label":"Say \"hi\"","url":"https://example.com"}]}"#,
+            )
+        );
+    }
+}
@@ -4,42 +4,210 @@ mod mappings;
 mod rpc;
 mod util;
 
-use rpc::activity::ActivityButton;
+use mappings::{get_by_filetype, Filetype};
+use rpc::activity::{Activity, ActivityButton};
 use std::{
+    collections::HashMap,
     ffi::{c_char, CString},
-    ptr::null,
-    time::UNIX_EPOCH,
+    path::PathBuf,
+    ptr::{addr_of, addr_of_mut, null},
+    sync::Mutex,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+use util::cache::Cache;
+use util::types::{
+    AssetType, BufferInfo, Layout, MissingIcon, PresenceOutcome, PresenceParts,
+    TimestampScope, WorkspacePrivacy,
 };
-use util::types::AssetType;
 use util::utils::{
-    build_activity, build_presence, find_workspace, get_asset, ptr_to_string,
-    validate_buttons,
+    advance_ticker, asset_url, asset_urls, build_focus_activity,
+    build_idle_activity, build_presence, debounce_read_only, display_filename,
+    expand_details, find_workspace, get_asset, is_blacklisted_path,
+    is_ignored_buffer, is_unnamed_buffer, language_details, missing_icon,
+    parse_list, parse_map, presence_activity, preview_presence,
+    ptr_to_display_name, ptr_to_string, refresh_git_branch,
+    refresh_git_changes, refresh_package, refresh_repository,
+    refresh_workspace, replace_placeholder, resolve_icon, scoped_timestamp,
+    track_last_command, unnamed_presence, validate_buttons, workspace_basename,
+    MAX_RECENT_FILES,
 };
 
 use crate::{
-    ipc::client::{Connection, RichClient},
+    ipc::client::{
+        Connection, ConnectionError, ConnectionEvent, RichClient,
+        DISCORD_BUILD, IPC_PATH,
+    },
     rpc::packet::Packet,
 };
 
 static mut INITIALIZED: bool = false;
 static mut START_TIME: Option<u128> = None;
 static mut CONFIG: Option<Config> = None;
+static mut LAST_CONNECTED: Option<Instant> = None;
+static mut HAS_FOCUSED: bool = false;
+static EVENTS: Mutex<Vec<ConnectionEvent>> = Mutex::new(Vec::new());
+static LAST_ERROR: Mutex<Option<ConnectionError>> = Mutex::new(None);
+const GIT_CACHE_CAPACITY: usize = 8;
+/// Editors with an image in the assets directory.
+const BUNDLED_EDITORS: [&str; 5] =
+    ["vim", "neovim", "lunarvim", "nvchad", "astronvim"];
+
+/// The config set up by `init`. Only Neovim's main thread calls into the
+/// library, so the statics are never accessed concurrently.
+#[inline(always)]
+unsafe fn config() -> Option<&'static mut Config> {
+    (*addr_of_mut!(CONFIG)).as_mut()
+}
+
+#[inline(always)]
+unsafe fn start_time() -> Option<&'static u128> {
+    (*addr_of!(START_TIME)).as_ref()
+}
+
+/// Stores the editor state sent along with every update and refreshes what
+/// is derived from the current file. Returns whether the buffer counts as
+/// read-only once debounced.
+fn apply_presence_args(
+    config: &mut Config,
+    args: &PresenceArgs,
+    filepath: &str,
+) -> bool {
+    let non_empty = |ptr: *const c_char| {
+        Some(ptr_to_string(ptr)).filter(|text| !text.is_empty())
+    };
+    let lines = |ptr: *const c_char| {
+        ptr_to_string(ptr)
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    config.toolchain = non_empty(args.toolchain);
+    config.symbol = non_empty(args.symbol);
+    config.colorscheme = non_empty(args.colorscheme);
+    // A buffer always has at least one line, zero means not provided
+    config.line_count = u32::try_from(args.line_count)
+        .ok()
+        .filter(|&count| count > 0);
+    config.languages = lines(args.languages);
+    config.recent_files = lines(args.recent_files);
+    config.recent_files.retain(|file| !file.is_empty());
+    config.recent_files.truncate(MAX_RECENT_FILES);
+    advance_ticker(config);
+
+    let now = Instant::now();
+    track_last_command(config, non_empty(args.last_command), now);
+    let is_read_only =
+        debounce_read_only(config, filepath, args.is_read_only, now);
+    refresh_workspace(config, filepath);
+    refresh_git_branch(config, now);
+    refresh_repository(config);
+    refresh_git_changes(config);
+    refresh_package(config, filepath);
+
+    is_read_only
+}
 
 struct Config {
     rich_client: RichClient,
+    editor: String,
     editor_image: String,
     editor_tooltip: String,
+    /// The per-editor overrides `editor_image` and `editor_tooltip` were
+    /// picked from.
+    editor_images: HashMap<String, String>,
+    editor_tooltips: HashMap<String, String>,
     idle_text: String,
     idle_tooltip: String,
+    idle_image: String,
     viewing_text: String,
     editing_text: String,
     file_browser_text: String,
     plugin_manager_text: String,
     lsp_manager_text: String,
+    dashboard_text: String,
+    unnamed_text: String,
+    unnamed_icon: String,
     workspace_text: String,
+    config_editing_text: String,
+    config_dir: String,
+    streaming_text: String,
+    streaming_url: String,
+    languages_delimiter: String,
+    languages_max: usize,
+    problem_text: String,
+    problem_text_singular: String,
+    toolchain: Option<String>,
+    symbol: Option<String>,
+    languages: Vec<String>,
+    last_command: Option<String>,
+    /// When `last_command` was run, it is hidden again after
+    /// `last_command_duration`.
+    last_command_at: Option<Instant>,
+    last_command_duration: Duration,
+    line_count: Option<u32>,
+    colorscheme: Option<String>,
+    read_only: bool,
+    read_only_file: String,
+    read_only_since: Option<Instant>,
+    readonly_debounce: Duration,
+    max_details_length: usize,
+    cursor_position_format: String,
+    recent_files: Vec<String>,
+    recent_file: Option<String>,
+    ticker_index: usize,
+    focus_mode: bool,
+    /// The activity shown before focus mode, restored when it ends.
+    unfocused_activity: Option<Activity>,
     workspace: String,
+    workspace_path: String,
+    workspace_cache: Cache<PathBuf>,
+    git_branch: Option<String>,
+    git_commit: Option<String>,
+    git_remote: String,
+    branch_cache: Cache<(Option<String>, Option<String>)>,
+    package: Option<String>,
+    package_cache: Cache<Option<String>>,
     buttons: Vec<ActivityButton>,
-    swap_fields: bool,
+    git_hosts: HashMap<String, String>,
+    repository: Option<String>,
+    repository_cache: Cache<Option<String>>,
+    git_changes: Option<usize>,
+    changes_cache: Cache<Option<usize>>,
+    dirty_text: String,
+    count_git_changes: bool,
+    icon_variants: HashMap<String, String>,
+    filetype_aliases: HashMap<String, String>,
+    path_blacklist: Vec<String>,
+    ignored_filetypes: Vec<String>,
+    ignored_files: Vec<String>,
+    assets_url: String,
+    assets_version: String,
+    workspace_markers: Vec<String>,
+    lsp_root_markers: Vec<String>,
+    layout: Layout,
+    require_focus: bool,
+    use_package_root: bool,
+    clear_on_empty: bool,
+    recompute_workspace_always: bool,
+    on_missing_icon: MissingIcon,
+    default_icon: String,
+    default_tooltip: String,
+    timestamp_scope: TimestampScope,
+    timestamp_file: String,
+    file_start: Option<u128>,
+    timestamp_workspace: String,
+    workspace_start: Option<u128>,
+    end_time: Option<u128>,
+    end_replaces_start: bool,
+    sticky_code_presence: bool,
+    recent_files_ticker: bool,
+    workspace_privacy: WorkspacePrivacy,
+    workspace_placeholder: String,
+    show_workspace_path: bool,
+    relative_filename: bool,
+    /// Top-level switch set by `set_enabled`.
+    enabled: bool,
 }
 
 #[repr(C)]
@@ -55,65 +223,231 @@ pub struct InitArgs {
     pub client: *const c_char,
     pub image: *const c_char,
     pub editor_tooltip: *const c_char,
+    pub editor_version: *const c_char,
     pub idle_text: *const c_char,
     pub idle_tooltip: *const c_char,
+    pub idle_image: *const c_char,
     pub viewing_text: *const c_char,
     pub editing_text: *const c_char,
     pub file_browser_text: *const c_char,
     pub plugin_manager_text: *const c_char,
     pub lsp_manager_text: *const c_char,
     pub workspace_text: *const c_char,
+    pub config_editing_text: *const c_char,
     pub initial_path: *const c_char,
+    pub config_dir: *const c_char,
+    pub icon_variants: *const c_char,
+    pub assets_url: *const c_char,
+    pub assets_version: *const c_char,
+    pub workspace_markers: *const c_char,
+    pub lsp_root_markers: *const c_char,
+    pub streaming_text: *const c_char,
+    pub streaming_url: *const c_char,
+    pub languages_delimiter: *const c_char,
+    pub languages_max: u32,
+    pub problem_text: *const c_char,
+    pub problem_text_singular: *const c_char,
+    pub reconnect_interval: u32,
+    pub connect_attempts: u32,
+    pub connect_delay: u32,
+    pub timestamp_scope: *const c_char,
+    pub git_cache_ttl: u32,
+    pub git_remote: *const c_char,
+    pub git_hosts: *const c_char,
+    pub readonly_debounce: u32,
+    pub max_details_length: u32,
+    pub cursor_position_format: *const c_char,
     pub swap_fields: bool,
+    pub layout: *const c_char,
+    pub require_focus: bool,
+    pub use_package_root: bool,
+    pub clear_on_empty: bool,
+    pub recompute_workspace_always: bool,
+    pub on_missing_icon: *const c_char,
+    pub default_icon: *const c_char,
+    pub default_tooltip: *const c_char,
+    pub sticky_code_presence: bool,
+    pub recent_files_ticker: bool,
+    pub workspace_privacy: *const c_char,
+    pub workspace_placeholder: *const c_char,
+    pub show_workspace_path: bool,
+    pub ignore_timestamp_changes: bool,
+    pub dirty_text: *const c_char,
+    pub check_responses: bool,
+    pub ipc_path: *const c_char,
+    pub discord_build: *const c_char,
+    pub relative_filename: bool,
+    pub filetype_aliases: *const c_char,
+    pub editor: *const c_char,
+    pub editor_images: *const c_char,
+    pub editor_tooltips: *const c_char,
+    pub path_blacklist: *const c_char,
+    pub ignored_filetypes: *const c_char,
+    pub ignored_files: *const c_char,
+    pub dashboard_text: *const c_char,
+    pub unnamed_text: *const c_char,
+    pub unnamed_icon: *const c_char,
+    pub git_changes: bool,
+    pub last_command_duration: u32,
 }
 
 #[repr(C)]
 pub struct PresenceArgs {
     pub filename: *const c_char,
+    pub filepath: *const c_char,
     pub filetype: *const c_char,
     pub cursor_position: *const c_char,
     pub problem_count: i32,
     pub is_read_only: bool,
+    pub is_streaming: bool,
+    pub toolchain: *const c_char,
+    pub symbol: *const c_char,
+    pub is_empty_session: bool,
+    pub languages: *const c_char,
+    pub last_command: *const c_char,
+    pub encoding: *const c_char,
+    pub recent_files: *const c_char,
+    pub line_count: i32,
+    pub should_clear: bool,
+    pub colorscheme: *const c_char,
 }
 
+/// # Safety
+/// `args_ptr` must point to valid `InitArgs` and `buttons_ptr` must be
+/// either null or point to valid `Buttons`.
 #[no_mangle]
-pub extern "C" fn init(args_ptr: *const InitArgs, buttons_ptr: *const Buttons) {
+pub unsafe extern "C" fn init(
+    args_ptr: *const InitArgs,
+    buttons_ptr: *const Buttons,
+) {
     unsafe {
         if INITIALIZED {
             return;
         }
 
         let args = &*args_ptr;
+        let assets_url = ptr_to_string(args.assets_url);
+        let assets_version = ptr_to_string(args.assets_version);
+        let editor_asset = |name: &str| {
+            asset_url(&assets_url, &assets_version, "editor", name)
+        };
 
-        let (client_id, client_image) = match ptr_to_string(args.client)
-            .as_str()
-        {
-            "vim" => (1219918645770059796, get_asset("editor", "vim")),
-            "neovim" => (1219918880005165137, get_asset("editor", "neovim")),
-            "lunarvim" => {
-                (1220295374087000104, get_asset("editor", "lunarvim"))
-            }
-            "nvchad" => (1220296082861326378, get_asset("editor", "nvchad")),
-            "astronvim" => {
-                (1230866983977746532, get_asset("editor", "astronvim"))
+        let (client_id, client_image) =
+            match ptr_to_string(args.client).as_str() {
+                "vim" => (1219918645770059796, editor_asset("vim")),
+                "neovim" => (1219918880005165137, editor_asset("neovim")),
+                "lunarvim" => (1220295374087000104, editor_asset("lunarvim")),
+                "nvchad" => (1220296082861326378, editor_asset("nvchad")),
+                "astronvim" => (1230866983977746532, editor_asset("astronvim")),
+                id => (
+                    id.parse::<u64>().expect("Invalid client ID"),
+                    ptr_to_string(args.image),
+                ),
+            };
+
+        // The image and tooltip picked for the running editor take priority
+        // over the ones of the client
+        let editor = ptr_to_string(args.editor);
+        let editor_images = parse_map(&ptr_to_string(args.editor_images));
+        let editor_tooltips = parse_map(&ptr_to_string(args.editor_tooltips));
+        let client_image = match editor_images.get(&editor) {
+            Some(image) if BUNDLED_EDITORS.contains(&image.as_str()) => {
+                editor_asset(image)
             }
-            id => (
-                id.parse::<u64>().expect("Invalid client ID"),
-                ptr_to_string(args.image),
-            ),
+            Some(image) => image.clone(),
+            None => client_image,
         };
+        let editor_tooltip = editor_tooltips
+            .get(&editor)
+            .cloned()
+            .unwrap_or_else(|| ptr_to_string(args.editor_tooltip));
 
-        let editor_tooltip = ptr_to_string(args.editor_tooltip);
+        let editor_version = (!args.editor_version.is_null())
+            .then(|| ptr_to_string(args.editor_version));
+        let editor_tooltip = replace_placeholder(
+            &editor_tooltip,
+            "version",
+            editor_version.as_deref(),
+        );
         let idle_text = ptr_to_string(args.idle_text);
         let idle_tooltip = ptr_to_string(args.idle_tooltip);
+        let idle_image = ptr_to_string(args.idle_image);
         let viewing_text = ptr_to_string(args.viewing_text);
         let editing_text = ptr_to_string(args.editing_text);
         let file_browser_text = ptr_to_string(args.file_browser_text);
         let plugin_manager_text = ptr_to_string(args.plugin_manager_text);
         let lsp_manager_text = ptr_to_string(args.lsp_manager_text);
+        let dashboard_text = ptr_to_string(args.dashboard_text);
+        let unnamed_text = ptr_to_string(args.unnamed_text);
+        let unnamed_icon = ptr_to_string(args.unnamed_icon);
         let workspace_text = ptr_to_string(args.workspace_text);
-        let swap_fields = args.swap_fields;
-        let workspace = find_workspace(&ptr_to_string(args.initial_path));
+        let config_editing_text = ptr_to_string(args.config_editing_text);
+        let config_dir = ptr_to_string(args.config_dir);
+        let icon_variants = parse_map(&ptr_to_string(args.icon_variants));
+        let streaming_text = ptr_to_string(args.streaming_text);
+        let streaming_url = ptr_to_string(args.streaming_url);
+        let languages_delimiter = ptr_to_string(args.languages_delimiter);
+        let languages_max = args.languages_max as usize;
+        let problem_text = ptr_to_string(args.problem_text);
+        let problem_text_singular = ptr_to_string(args.problem_text_singular);
+        let layout =
+            Layout::from(&ptr_to_string(args.layout), args.swap_fields);
+        let require_focus = args.require_focus;
+        let use_package_root = args.use_package_root;
+        let clear_on_empty = args.clear_on_empty;
+        let recompute_workspace_always = args.recompute_workspace_always;
+        let on_missing_icon =
+            MissingIcon::from(&ptr_to_string(args.on_missing_icon));
+        let default_icon = ptr_to_string(args.default_icon);
+        let default_tooltip = ptr_to_string(args.default_tooltip);
+        let sticky_code_presence = args.sticky_code_presence;
+        let recent_files_ticker = args.recent_files_ticker;
+        let workspace_privacy =
+            WorkspacePrivacy::from(&ptr_to_string(args.workspace_privacy));
+        let workspace_placeholder = ptr_to_string(args.workspace_placeholder);
+        let show_workspace_path = args.show_workspace_path;
+        let relative_filename = args.relative_filename;
+        let filetype_aliases = parse_map(&ptr_to_string(args.filetype_aliases));
+        let ignore_timestamp_changes = args.ignore_timestamp_changes;
+        let dirty_text = ptr_to_string(args.dirty_text);
+        let count_git_changes = args.git_changes;
+        let last_command_duration =
+            Duration::from_millis(args.last_command_duration as u64);
+        let check_responses = args.check_responses;
+        if let Ok(mut ipc_path) = IPC_PATH.lock() {
+            *ipc_path = Some(ptr_to_string(args.ipc_path))
+                .filter(|path| !path.is_empty());
+        }
+        if let Ok(mut discord_build) = DISCORD_BUILD.lock() {
+            *discord_build = Some(ptr_to_string(args.discord_build))
+                .filter(|build| !build.is_empty());
+        }
+        let reconnect_interval =
+            Duration::from_millis(args.reconnect_interval as u64);
+        let connect_attempts = args.connect_attempts;
+        let connect_delay = Duration::from_millis(args.connect_delay as u64);
+        let timestamp_scope =
+            TimestampScope::from(&ptr_to_string(args.timestamp_scope));
+        let git_cache_ttl = Duration::from_millis(args.git_cache_ttl as u64);
+        let git_remote = ptr_to_string(args.git_remote);
+        let git_hosts = parse_map(&ptr_to_string(args.git_hosts));
+        let readonly_debounce =
+            Duration::from_millis(args.readonly_debounce as u64);
+        let max_details_length = args.max_details_length as usize;
+        let cursor_position_format = ptr_to_string(args.cursor_position_format);
+        let workspace_markers =
+            parse_list(&ptr_to_string(args.workspace_markers));
+        let lsp_root_markers =
+            parse_list(&ptr_to_string(args.lsp_root_markers));
+        let path_blacklist = parse_list(&ptr_to_string(args.path_blacklist));
+        let ignored_filetypes =
+            parse_list(&ptr_to_string(args.ignored_filetypes));
+        let ignored_files = parse_list(&ptr_to_string(args.ignored_files));
+        let workspace = find_workspace(
+            &ptr_to_string(args.initial_path),
+            &workspace_markers,
+            &lsp_root_markers,
+        );
 
         let buttons = if buttons_ptr.is_null() {
             Vec::new()
@@ -124,104 +458,257 @@ pub extern "C" fn init(args_ptr: *const InitArgs, buttons_ptr: *const Buttons) {
                 ptr_to_string(buttons.first_url),
                 ptr_to_string(buttons.second_label),
                 ptr_to_string(buttons.second_url),
-                workspace.to_str().unwrap(),
             )
         };
 
         std::thread::spawn(move || {
-            if let Ok(mut client) = RichClient::connect(client_id) {
-                client
-                    .handshake()
-                    .expect("Failed to handshake with Rich Client");
-                client.read().expect("Failed to read from Rich Client");
+            let client = establish(reconnect_interval, || {
+                RichClient::connect(client_id, connect_attempts, connect_delay)
+            });
+            if let Some(mut client) = client {
+                client.ignore_timestamp_changes = ignore_timestamp_changes;
+                client.check_responses = check_responses;
 
                 CONFIG = Some(Config {
                     rich_client: client,
+                    editor,
                     editor_image: client_image,
-                    editor_tooltip: editor_tooltip,
-                    idle_text: idle_text,
-                    idle_tooltip: idle_tooltip,
-                    viewing_text: viewing_text,
-                    editing_text: editing_text,
-                    file_browser_text: file_browser_text,
-                    plugin_manager_text: plugin_manager_text,
-                    lsp_manager_text: lsp_manager_text,
-                    workspace_text: workspace_text,
-                    workspace: workspace
-                        .file_name()
-                        .unwrap()
-                        .to_string_lossy()
-                        .to_string(),
-                    buttons: buttons,
-                    swap_fields: swap_fields,
+                    editor_tooltip,
+                    editor_images,
+                    editor_tooltips,
+                    idle_text,
+                    idle_tooltip,
+                    idle_image,
+                    viewing_text,
+                    editing_text,
+                    file_browser_text,
+                    plugin_manager_text,
+                    lsp_manager_text,
+                    dashboard_text,
+                    unnamed_text,
+                    unnamed_icon,
+                    workspace_text,
+                    config_editing_text,
+                    config_dir,
+                    streaming_text,
+                    streaming_url,
+                    languages_delimiter,
+                    languages_max,
+                    problem_text,
+                    problem_text_singular,
+                    toolchain: None,
+                    symbol: None,
+                    languages: Vec::new(),
+                    last_command: None,
+                    last_command_at: None,
+                    last_command_duration,
+                    line_count: None,
+                    colorscheme: None,
+                    read_only: false,
+                    read_only_file: String::new(),
+                    read_only_since: None,
+                    readonly_debounce,
+                    max_details_length,
+                    cursor_position_format,
+                    recent_files: Vec::new(),
+                    recent_file: None,
+                    ticker_index: 0,
+                    focus_mode: false,
+                    unfocused_activity: None,
+                    workspace: workspace_basename(&workspace),
+                    workspace_path: workspace.to_string_lossy().to_string(),
+                    workspace_cache: Cache::new(
+                        GIT_CACHE_CAPACITY,
+                        Duration::MAX,
+                    ),
+                    git_branch: None,
+                    git_commit: None,
+                    git_remote,
+                    branch_cache: Cache::new(GIT_CACHE_CAPACITY, git_cache_ttl),
+                    package: None,
+                    package_cache: Cache::new(
+                        GIT_CACHE_CAPACITY,
+                        git_cache_ttl,
+                    ),
+                    buttons,
+                    git_hosts,
+                    repository: None,
+                    repository_cache: Cache::new(
+                        GIT_CACHE_CAPACITY,
+                        Duration::MAX,
+                    ),
+                    git_changes: None,
+                    changes_cache: Cache::new(
+                        GIT_CACHE_CAPACITY,
+                        git_cache_ttl,
+                    ),
+                    dirty_text,
+                    count_git_changes,
+                    icon_variants,
+                    filetype_aliases,
+                    path_blacklist,
+                    ignored_filetypes,
+                    ignored_files,
+                    assets_url,
+                    assets_version,
+                    workspace_markers,
+                    lsp_root_markers,
+                    layout,
+                    require_focus,
+                    use_package_root,
+                    clear_on_empty,
+                    recompute_workspace_always,
+                    on_missing_icon,
+                    default_icon,
+                    default_tooltip,
+                    timestamp_scope,
+                    timestamp_file: String::new(),
+                    file_start: None,
+                    timestamp_workspace: String::new(),
+                    workspace_start: None,
+                    end_time: None,
+                    end_replaces_start: false,
+                    sticky_code_presence,
+                    recent_files_ticker,
+                    workspace_privacy,
+                    workspace_placeholder,
+                    show_workspace_path,
+                    relative_filename,
+                    enabled: true,
                 });
                 INITIALIZED = true;
-            };
+            }
         });
     }
 }
 
+/// Connects to Discord, recording the connection events along the way. Only
+/// reconnects are throttled, the first connection is immediate.
+unsafe fn establish(
+    reconnect_interval: Duration,
+    connect: impl FnOnce() -> Result<RichClient, ConnectionError>,
+) -> Option<RichClient> {
+    if let Some(last_connected) = LAST_CONNECTED {
+        record_event(ConnectionEvent::Reconnecting);
+        let elapsed = last_connected.elapsed();
+        if elapsed < reconnect_interval {
+            std::thread::sleep(reconnect_interval - elapsed);
+        }
+    }
+
+    match connect() {
+        Ok(client) => {
+            LAST_CONNECTED = Some(Instant::now());
+            record_event(ConnectionEvent::Connected);
+            Some(client)
+        }
+        Err(e) => {
+            let is_refused = matches!(e, ConnectionError::Handshake { .. });
+            record_error(e);
+            if is_refused {
+                record_event(ConnectionEvent::HandshakeFailed);
+            } else if !RichClient::is_installed() {
+                record_event(ConnectionEvent::NotInstalled);
+            }
+            None
+        }
+    }
+}
+
+/// # Safety
+/// `args_ptr` must point to valid `PresenceArgs`.
 #[no_mangle]
-pub extern "C" fn update_presence(args_ptr: *const PresenceArgs) -> bool {
+pub unsafe extern "C" fn update_presence(
+    args_ptr: *const PresenceArgs,
+) -> bool {
     unsafe {
         if !INITIALIZED {
             return false;
         }
 
-        CONFIG.as_mut().map_or(false, |config| {
+        config().is_some_and(|config| {
+            if !config.enabled
+                || config.focus_mode
+                || (config.require_focus && !HAS_FOCUSED)
+            {
+                return true;
+            }
+
             let args = &*args_ptr;
-            let filename = ptr_to_string(args.filename);
+            if args.should_clear {
+                return config.rich_client.clear().is_ok();
+            }
+
+            let filename = ptr_to_display_name(
+                args.filename,
+                &ptr_to_string(args.encoding),
+            );
+            let filepath = ptr_to_string(args.filepath);
             let filetype = ptr_to_string(args.filetype);
             let cursor_position = if !args.cursor_position.is_null() {
                 Some(ptr_to_string(args.cursor_position))
             } else {
                 None
             };
+            let is_read_only = apply_presence_args(config, args, &filepath);
+            if is_blacklisted_path(config) {
+                return config.rich_client.clear().is_ok();
+            }
 
-            let (details, large_image, large_text) = if filetype == "Cord.idle"
-            {
+            let activity = if filetype == "Cord.idle" {
                 if config.idle_text.is_empty() {
                     return false;
                 }
 
-                (
-                    config.idle_text.clone(),
-                    Some(get_asset("editor", "idle")),
-                    config.idle_tooltip.clone(),
+                build_idle_activity(
+                    config,
+                    args.problem_count,
+                    start_time(),
+                    config.layout,
+                    args.is_streaming,
                 )
             } else {
-                build_presence(
-                    &config,
+                match build_presence(
+                    config,
                     &filename,
+                    &filepath,
                     &filetype,
-                    args.is_read_only,
+                    is_read_only,
                     cursor_position.as_deref(),
-                )
+                    args.is_empty_session,
+                ) {
+                    PresenceOutcome::Present(parts) => {
+                        let timestamp =
+                            scoped_timestamp(config, &filepath, START_TIME);
+                        presence_activity(
+                            config,
+                            parts,
+                            args.problem_count,
+                            &filetype,
+                            timestamp.as_ref(),
+                            args.is_streaming,
+                        )
+                    }
+                    PresenceOutcome::Clear => {
+                        return config.rich_client.clear().is_ok();
+                    }
+                    PresenceOutcome::NoChange => return true,
+                }
             };
 
-            let activity = build_activity(
-                config,
-                details,
-                large_image,
-                large_text,
-                args.problem_count,
-                START_TIME.as_ref(),
-                config.swap_fields,
-            );
-
-            match config
-                .rich_client
-                .update(&Packet::new(std::process::id(), Some(activity)))
-            {
-                Ok(_) => true,
-                Err(_) => false,
-            }
+            is_sent(
+                config
+                    .rich_client
+                    .update(&Packet::new(std::process::id(), Some(activity))),
+            )
         })
     }
 }
 
+/// # Safety
+/// `args_ptr` must point to valid `PresenceArgs`.
 #[no_mangle]
-pub extern "C" fn update_presence_with_assets(
+pub unsafe extern "C" fn update_presence_with_assets(
     name: *const c_char,
     icon: *const c_char,
     tooltip: *const c_char,
@@ -233,9 +720,35 @@ pub extern "C" fn update_presence_with_assets(
             return false;
         }
 
-        CONFIG.as_mut().map_or(false, |config| {
+        config().is_some_and(|config| {
+            if !config.enabled
+                || config.focus_mode
+                || (config.require_focus && !HAS_FOCUSED)
+            {
+                return true;
+            }
+
             let args = &*args_ptr;
-            let filename = ptr_to_string(args.filename);
+            // Every real buffer has been closed, only the empty one remains
+            if args.should_clear
+                || (args.is_empty_session && config.clear_on_empty)
+            {
+                return config.rich_client.clear().is_ok();
+            }
+
+            if config.sticky_code_presence
+                && !matches!(
+                    AssetType::from(asset_type),
+                    Some(AssetType::Language)
+                )
+            {
+                return true;
+            }
+
+            let filename = ptr_to_display_name(
+                args.filename,
+                &ptr_to_string(args.encoding),
+            );
             let filetype = ptr_to_string(args.filetype);
             let name = ptr_to_string(name);
             let mut icon = ptr_to_string(icon);
@@ -245,42 +758,60 @@ pub extern "C" fn update_presence_with_assets(
             } else {
                 None
             };
+            let filepath = ptr_to_string(args.filepath);
+            let is_read_only = apply_presence_args(config, args, &filepath);
+            if is_blacklisted_path(config)
+                || is_ignored_buffer(config, &filename, &filetype)
+            {
+                return config.rich_client.clear().is_ok();
+            }
 
             let (details, large_image, large_text) =
                 match AssetType::from(asset_type) {
+                    Some(AssetType::Language)
+                        if is_unnamed_buffer(&filename, &filetype) =>
+                    {
+                        let parts = unnamed_presence(config, &filetype);
+                        (parts.details, parts.large_image, parts.large_text)
+                    }
                     Some(AssetType::Language) => {
                         let filename = if !filename.is_empty() {
                             &filename
+                        } else if !name.is_empty() {
+                            &name
                         } else {
-                            if !name.is_empty() && name != "Cord.new" {
-                                &name
-                            } else {
-                                "a new file"
-                            }
+                            "a new file"
                         };
-                        let details = if args.is_read_only {
-                            config.viewing_text.replace("{}", filename)
-                        } else {
-                            config.editing_text.replace("{}", filename)
-                        };
-                        let details = cursor_position
-                            .map_or(details.clone(), |pos| {
-                                format!("{}:{}", details, pos)
-                            });
+                        let details = language_details(
+                            config,
+                            &display_filename(config, filename, &filepath),
+                            &filetype,
+                            is_read_only,
+                            cursor_position.as_deref(),
+                        );
 
                         if icon.is_empty() || tooltip.is_empty() {
-                            if let Some((default_icon, default_tooltip)) =
-                                mappings::language::get(&filetype, filename)
-                            {
+                            if let Filetype::Language(
+                                default_icon,
+                                default_tooltip,
+                            ) = get_by_filetype(
+                                &filetype,
+                                filename,
+                                &config.filetype_aliases,
+                            ) {
                                 if icon.is_empty() {
-                                    icon = get_asset("language", default_icon);
+                                    icon = get_asset(
+                                        config,
+                                        "language",
+                                        &resolve_icon(config, default_icon),
+                                    );
                                 }
                                 if tooltip.is_empty() {
                                     tooltip = default_tooltip.to_string();
                                 }
                             } else {
                                 if icon.is_empty() {
-                                    return false;
+                                    icon = missing_icon(config);
                                 }
                                 if tooltip.is_empty() {
                                     tooltip = name;
@@ -288,19 +819,26 @@ pub extern "C" fn update_presence_with_assets(
                             }
                         }
 
-                        (details, icon, tooltip)
+                        (details, Some(icon), tooltip)
                     }
                     Some(AssetType::FileBrowser) => {
-                        let details =
-                            config.file_browser_text.replace("{}", &name);
+                        let details = expand_details(
+                            config,
+                            &config.file_browser_text,
+                            &filetype,
+                        )
+                        .replace("{}", &name);
 
                         if icon.is_empty() || tooltip.is_empty() {
                             if let Some((default_icon, default_tooltip)) =
                                 mappings::file_browser::get(&filetype)
                             {
                                 if icon.is_empty() {
-                                    icon =
-                                        get_asset("file_browser", default_icon);
+                                    icon = get_asset(
+                                        config,
+                                        "file_browser",
+                                        default_icon,
+                                    );
                                 }
                                 if tooltip.is_empty() {
                                     tooltip = default_tooltip.to_string();
@@ -315,11 +853,15 @@ pub extern "C" fn update_presence_with_assets(
                             }
                         }
 
-                        (details, icon, tooltip)
+                        (details, Some(icon), tooltip)
                     }
                     Some(AssetType::PluginManager) => {
-                        let details =
-                            config.plugin_manager_text.replace("{}", &name);
+                        let details = expand_details(
+                            config,
+                            &config.plugin_manager_text,
+                            &filetype,
+                        )
+                        .replace("{}", &name);
 
                         if icon.is_empty() || tooltip.is_empty() {
                             if let Some((default_icon, default_tooltip)) =
@@ -327,6 +869,7 @@ pub extern "C" fn update_presence_with_assets(
                             {
                                 if icon.is_empty() {
                                     icon = get_asset(
+                                        config,
                                         "plugin_manager",
                                         default_icon,
                                     );
@@ -344,19 +887,26 @@ pub extern "C" fn update_presence_with_assets(
                             }
                         }
 
-                        (details, icon, tooltip)
+                        (details, Some(icon), tooltip)
                     }
-                    Some(AssetType::LSP) => {
-                        let details =
-                            config.lsp_manager_text.replace("{}", &name);
+                    Some(AssetType::Lsp) => {
+                        let details = expand_details(
+                            config,
+                            &config.lsp_manager_text,
+                            &filetype,
+                        )
+                        .replace("{}", &name);
 
                         if icon.is_empty() || tooltip.is_empty() {
                             if let Some((default_icon, default_tooltip)) =
                                 mappings::lsp_manager::get(&filetype)
                             {
                                 if icon.is_empty() {
-                                    icon =
-                                        get_asset("lsp_manager", default_icon);
+                                    icon = get_asset(
+                                        config,
+                                        "lsp_manager",
+                                        default_icon,
+                                    );
                                 }
                                 if tooltip.is_empty() {
                                     tooltip = default_tooltip.to_string();
@@ -371,32 +921,274 @@ pub extern "C" fn update_presence_with_assets(
                             }
                         }
 
-                        (details, icon, tooltip)
+                        (details, Some(icon), tooltip)
+                    }
+                    Some(AssetType::Dashboard) => {
+                        let details = expand_details(
+                            config,
+                            &config.dashboard_text,
+                            &filetype,
+                        )
+                        .replace("{}", &name);
+
+                        if icon.is_empty() || tooltip.is_empty() {
+                            if let Some((default_icon, default_tooltip)) =
+                                mappings::dashboard::get(&filetype)
+                            {
+                                if icon.is_empty() {
+                                    icon = get_asset(
+                                        config,
+                                        "dashboard",
+                                        default_icon,
+                                    );
+                                }
+                                if tooltip.is_empty() {
+                                    tooltip = default_tooltip.to_string();
+                                }
+                            } else {
+                                if icon.is_empty() {
+                                    return false;
+                                }
+                                if tooltip.is_empty() {
+                                    tooltip = name;
+                                }
+                            }
+                        }
+
+                        (details, Some(icon), tooltip)
                     }
                     None => return false,
                 };
 
-            let activity = build_activity(
+            let timestamp = scoped_timestamp(config, &filepath, START_TIME);
+            let activity = presence_activity(
                 config,
-                details,
-                Some(large_image),
-                large_text,
+                PresenceParts {
+                    details,
+                    large_image,
+                    large_text,
+                },
                 args.problem_count,
-                START_TIME.as_ref(),
-                config.swap_fields,
+                &filetype,
+                timestamp.as_ref(),
+                args.is_streaming,
             );
 
-            match config
-                .rich_client
-                .update(&Packet::new(std::process::id(), Some(activity)))
-            {
-                Ok(_) => true,
-                Err(_) => false,
+            is_sent(
+                config
+                    .rich_client
+                    .update(&Packet::new(std::process::id(), Some(activity))),
+            )
+        })
+    }
+}
+
+/// # Safety
+/// `args_ptr` must point to valid `PresenceArgs`.
+#[no_mangle]
+pub unsafe extern "C" fn get_presence_preview(
+    args_ptr: *const PresenceArgs,
+) -> *const c_char {
+    unsafe {
+        if let Some(config) = config() {
+            let args = &*args_ptr;
+            let cursor_position = if !args.cursor_position.is_null() {
+                Some(ptr_to_string(args.cursor_position))
+            } else {
+                None
+            };
+            let (filename, filepath, filetype) = (
+                ptr_to_string(args.filename),
+                ptr_to_string(args.filepath),
+                ptr_to_string(args.filetype),
+            );
+            let buffer = BufferInfo {
+                filename: &filename,
+                filepath: &filepath,
+                filetype: &filetype,
+                is_read_only: args.is_read_only,
+                cursor_position: cursor_position.as_deref(),
+            };
+            let activity = match preview_presence(
+                config,
+                &buffer,
+                args.problem_count,
+                start_time(),
+                args.is_streaming,
+            ) {
+                Some(activity) => activity,
+                None => return null(),
+            };
+
+            let mut json = String::new();
+            if activity.push_json(&mut json).is_ok() {
+                return CString::new(json).unwrap().into_raw() as *const c_char;
             }
+        }
+
+        null()
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn get_asset_urls() -> *const c_char {
+    unsafe {
+        match config() {
+            Some(config) => CString::new(asset_urls(config).join("\n"))
+                .unwrap()
+                .into_raw() as *const c_char,
+            None => null(),
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn get_config_json(redact: bool) -> *const c_char {
+    unsafe {
+        match config() {
+            Some(config) => CString::new(config.to_normalized_json(redact))
+                .unwrap()
+                .into_raw() as *const c_char,
+            None => null(),
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn enter_focus_mode(text: *const c_char) -> bool {
+    unsafe {
+        if !INITIALIZED {
+            return false;
+        }
+
+        config().is_some_and(|config| {
+            if !config.enabled {
+                return true;
+            }
+            let activity =
+                build_focus_activity(config, ptr_to_string(text), start_time());
+            if !config.focus_mode {
+                // An update held back by the rate limit is the latest one
+                config.unfocused_activity = match &config.rich_client.pending {
+                    Some(packet) => packet.activity.clone(),
+                    None => config.rich_client.last_activity.clone(),
+                };
+            }
+            config.focus_mode = true;
+
+            is_sent(
+                config
+                    .rich_client
+                    .update(&Packet::new(std::process::id(), Some(activity))),
+            )
         })
     }
 }
 
+/// Makes the following activities count down to `end`, in milliseconds since
+/// the epoch, alongside the elapsed time or in place of it. 0 removes it.
+#[no_mangle]
+pub extern "C" fn set_end_time(end: u64, replace_start: bool) {
+    unsafe {
+        if let Some(config) = config() {
+            config.end_time = Some(end as u128).filter(|end| *end > 0);
+            config.end_replaces_start = replace_start;
+        }
+    }
+}
+
+/// Shows an activity given as JSON in the shape written by `push_json`
+/// instead of the one built from the current buffer, until the next update.
+/// Returns false when the JSON is not a valid activity or it was not sent.
+#[no_mangle]
+pub extern "C" fn update_raw_presence(json: *const c_char) -> bool {
+    unsafe {
+        if !INITIALIZED {
+            return false;
+        }
+
+        let activity = match Activity::from_json(&ptr_to_string(json)) {
+            Some(activity) => activity,
+            None => return false,
+        };
+
+        config().is_some_and(|config| {
+            if !config.enabled {
+                return true;
+            }
+            is_sent(
+                config
+                    .rich_client
+                    .update(&Packet::new(std::process::id(), Some(activity))),
+            )
+        })
+    }
+}
+
+/// Leaves focus mode and shows the activity it replaced again, without
+/// waiting for the editor to change.
+#[no_mangle]
+pub extern "C" fn exit_focus_mode() -> bool {
+    unsafe {
+        config().is_some_and(|config| {
+            if !config.enabled || !config.focus_mode {
+                return true;
+            }
+            config.focus_mode = false;
+
+            match config.unfocused_activity.take() {
+                Some(activity) => {
+                    is_sent(config.rich_client.update(&Packet::new(
+                        std::process::id(),
+                        Some(activity),
+                    )))
+                }
+                None => config.rich_client.clear().is_ok(),
+            }
+        })
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn focus_gained() {
+    unsafe {
+        HAS_FOCUSED = true;
+    }
+}
+
+/// Whether the presence changes over time on its own, so it has to be sent
+/// again even if nothing in the editor changed.
+#[no_mangle]
+pub extern "C" fn needs_periodic_refresh() -> bool {
+    unsafe {
+        config().is_some_and(|config| {
+            !config.focus_mode
+                && (config.read_only_since.is_some()
+                    || config.last_command.is_some()
+                    || (config.recent_files_ticker
+                        && config.recent_files.len() > 1))
+        })
+    }
+}
+
+/// Top-level switch for the whole plugin. While disabled, updating and
+/// clearing do nothing and report success. Connecting while disabled is left
+/// to the Lua side, as there is no config yet to hold the switch.
+#[no_mangle]
+pub extern "C" fn set_enabled(enabled: bool) {
+    unsafe {
+        if let Some(config) =
+            config().filter(|config| config.enabled != enabled)
+        {
+            // Also forgets the last activity, so it is sent again once enabled
+            if !enabled {
+                let _ = config.rich_client.clear();
+            }
+            config.enabled = enabled;
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn clear_presence() {
     unsafe {
@@ -404,11 +1196,22 @@ pub extern "C" fn clear_presence() {
             return;
         }
 
-        if let Some(config) = CONFIG.as_mut() {
-            config
-                .rich_client
-                .clear()
-                .expect("Failed to clear presence");
+        if let Some(config) = config().filter(|config| config.enabled) {
+            is_sent(config.rich_client.clear());
+        }
+    }
+}
+
+/// Sends the update held back by the rate limit, if any.
+#[no_mangle]
+pub extern "C" fn flush_presence() {
+    unsafe {
+        if !INITIALIZED {
+            return;
+        }
+
+        if let Some(config) = config().filter(|config| config.enabled) {
+            is_sent(config.rich_client.flush());
         }
     }
 }
@@ -420,16 +1223,86 @@ pub extern "C" fn disconnect() {
             return;
         }
 
-        if let Some(mut config) = CONFIG.take() {
-            config
-                .rich_client
-                .close()
-                .expect("Failed to close connection");
+        if let Some(mut config) = (*addr_of_mut!(CONFIG)).take() {
+            if let Err(e) = config.rich_client.close() {
+                record_error(e);
+            }
             INITIALIZED = false;
+            record_event(ConnectionEvent::Disconnected);
         }
     }
 }
 
+/// The message of the last connection error, or `NULL` if there is none.
+#[no_mangle]
+pub extern "C" fn get_last_error() -> *const c_char {
+    match LAST_ERROR.lock() {
+        Ok(last_error) => match last_error.as_ref() {
+            Some(error) => CString::new(error.to_string()).unwrap().into_raw()
+                as *const c_char,
+            None => null(),
+        },
+        Err(_) => null(),
+    }
+}
+
+/// The kind of the last connection error: 1 when Discord was not found, 2 for
+/// a rejected handshake, 3 for I/O errors, 4 when rate limited, 5 for a
+/// rejected activity and 6 when Discord closed the connection. 0 if there is
+/// none.
+#[no_mangle]
+pub extern "C" fn get_last_error_code() -> i32 {
+    match LAST_ERROR.lock() {
+        Ok(last_error) => last_error.as_ref().map_or(0, ConnectionError::code),
+        Err(_) => 0,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn poll_connection_event() -> i32 {
+    match EVENTS.lock() {
+        Ok(mut events) if !events.is_empty() => events.remove(0) as i32,
+        _ => -1,
+    }
+}
+
+/// Reports a connection that broke and could not be re-established, so the
+/// Lua side can schedule a new connection instead of giving up.
+#[inline(always)]
+fn is_sent(result: Result<(), ConnectionError>) -> bool {
+    match result {
+        Ok(_) => true,
+        // Held back until the rate limit allows it, which is not a
+        // connection problem
+        Err(ConnectionError::RateLimited) => true,
+        // The connection is fine, Discord only refused this activity
+        Err(e @ ConnectionError::Rejected { .. }) => {
+            record_error(e);
+            record_event(ConnectionEvent::Rejected);
+            true
+        }
+        Err(e) => {
+            record_error(e);
+            record_event(ConnectionEvent::Lost);
+            false
+        }
+    }
+}
+
+#[inline(always)]
+fn record_error(error: ConnectionError) {
+    if let Ok(mut last_error) = LAST_ERROR.lock() {
+        *last_error = Some(error);
+    }
+}
+
+#[inline(always)]
+fn record_event(event: ConnectionEvent) {
+    if let Ok(mut events) = EVENTS.lock() {
+        events.push(event);
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn update_time() {
     unsafe {
@@ -446,14 +1319,20 @@ pub extern "C" fn update_time() {
 pub extern "C" fn update_workspace(value: *mut c_char) -> *const c_char {
     unsafe {
         let mut ws = String::new();
-        if let Some(config) = CONFIG.as_mut() {
-            if let Some(workspace) =
-                find_workspace(&ptr_to_string(value)).file_name()
-            {
-                let workspace = workspace.to_string_lossy().to_string();
-                ws = workspace.clone();
-                config.workspace = workspace;
-            }
+        if let Some(config) = config() {
+            let workspace_path = find_workspace(
+                &ptr_to_string(value),
+                &config.workspace_markers,
+                &config.lsp_root_markers,
+            );
+            let workspace = workspace_basename(&workspace_path);
+            ws = workspace.clone();
+            config.workspace = workspace;
+            config.workspace_path =
+                workspace_path.to_string_lossy().to_string();
+            config.branch_cache.invalidate(&config.workspace_path);
+            config.changes_cache.invalidate(&config.workspace_path);
+            config.repository_cache.invalidate(&config.workspace_path);
         }
 
         CString::new(ws).unwrap().into_raw() as *const c_char
@@ -463,7 +1342,7 @@ pub extern "C" fn update_workspace(value: *mut c_char) -> *const c_char {
 #[no_mangle]
 pub extern "C" fn get_workspace() -> *const c_char {
     unsafe {
-        if let Some(config) = CONFIG.as_ref() {
+        if let Some(config) = config() {
             CString::new(config.workspace.clone()).unwrap().into_raw()
                 as *const c_char
         } else {
@@ -471,3 +1350,386 @@ pub extern "C" fn get_workspace() -> *const c_char {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::client::RateLimit;
+
+    /// Serializes the tests that go through the statics behind the exports.
+    pub(crate) static STATICS: Mutex<()> = Mutex::new(());
+
+    /// A config with the defaults of the Lua side, not connected to Discord.
+    pub(crate) fn default_config() -> Config {
+        Config {
+            rich_client: RichClient {
+                client_id: 1219918880005165137,
+                pipe: None,
+                last_activity: None,
+                is_cleared: false,
+                rate_limit: RateLimit::default(),
+                pending: None,
+                ignore_timestamp_changes: false,
+                check_responses: false,
+            },
+            editor: String::new(),
+            editor_image: asset_url("", "", "editor", "neovim"),
+            editor_tooltip: "The Superior Text Editor".to_string(),
+            editor_images: HashMap::new(),
+            editor_tooltips: HashMap::new(),
+            idle_text: "Idle".to_string(),
+            idle_tooltip: "💤".to_string(),
+            idle_image: String::new(),
+            viewing_text: "Viewing {}".to_string(),
+            editing_text: "Editing {}".to_string(),
+            file_browser_text: "Browsing files in {}".to_string(),
+            plugin_manager_text: "Managing plugins in {}".to_string(),
+            lsp_manager_text: "Configuring LSP in {}".to_string(),
+            dashboard_text: "Browsing {}".to_string(),
+            unnamed_text: "Editing a new file".to_string(),
+            unnamed_icon: String::new(),
+            workspace_text: "In {}".to_string(),
+            config_editing_text: "Configuring Neovim".to_string(),
+            config_dir: "/home/user/.config/nvim".to_string(),
+            streaming_text: "Live on stream".to_string(),
+            streaming_url: String::new(),
+            languages_delimiter: ", ".to_string(),
+            languages_max: 3,
+            problem_text: " - {} problems".to_string(),
+            problem_text_singular: " - {} problem".to_string(),
+            toolchain: None,
+            symbol: None,
+            languages: Vec::new(),
+            last_command: None,
+            last_command_at: None,
+            last_command_duration: Duration::from_secs(5),
+            line_count: None,
+            colorscheme: None,
+            read_only: false,
+            read_only_file: String::new(),
+            read_only_since: None,
+            readonly_debounce: Duration::ZERO,
+            max_details_length: 128,
+            cursor_position_format: ":{line}:{col}".to_string(),
+            recent_files: Vec::new(),
+            recent_file: None,
+            ticker_index: 0,
+            focus_mode: false,
+            unfocused_activity: None,
+            workspace: "cord".to_string(),
+            workspace_path: "/home/user/cord".to_string(),
+            workspace_cache: Cache::new(GIT_CACHE_CAPACITY, Duration::MAX),
+            git_branch: None,
+            git_commit: None,
+            git_remote: "origin".to_string(),
+            branch_cache: Cache::new(
+                GIT_CACHE_CAPACITY,
+                Duration::from_secs(5),
+            ),
+            package: None,
+            package_cache: Cache::new(
+                GIT_CACHE_CAPACITY,
+                Duration::from_secs(5),
+            ),
+            buttons: Vec::new(),
+            git_hosts: HashMap::new(),
+            repository: None,
+            repository_cache: Cache::new(GIT_CACHE_CAPACITY, Duration::MAX),
+            git_changes: None,
+            changes_cache: Cache::new(
+                GIT_CACHE_CAPACITY,
+                Duration::from_secs(5),
+            ),
+            dirty_text: "{} changed".to_string(),
+            count_git_changes: false,
+            icon_variants: HashMap::new(),
+            filetype_aliases: HashMap::new(),
+            path_blacklist: Vec::new(),
+            ignored_filetypes: Vec::new(),
+            ignored_files: Vec::new(),
+            assets_url: String::new(),
+            assets_version: String::new(),
+            workspace_markers: vec![
+                ".git".to_string(),
+                ".svn".to_string(),
+                ".hg".to_string(),
+            ],
+            lsp_root_markers: Vec::new(),
+            layout: Layout::Default,
+            require_focus: false,
+            use_package_root: false,
+            clear_on_empty: false,
+            recompute_workspace_always: false,
+            on_missing_icon: MissingIcon::Text,
+            default_icon: String::new(),
+            default_tooltip: String::new(),
+            timestamp_scope: TimestampScope::Session,
+            timestamp_file: String::new(),
+            file_start: None,
+            timestamp_workspace: String::new(),
+            workspace_start: None,
+            end_time: None,
+            end_replaces_start: false,
+            sticky_code_presence: false,
+            recent_files_ticker: false,
+            workspace_privacy: WorkspacePrivacy::Off,
+            workspace_placeholder: "a project".to_string(),
+            show_workspace_path: false,
+            relative_filename: false,
+            enabled: true,
+        }
+    }
+
+    /// Installs `config` behind the exports with one end of a socket pair as
+    /// its pipe, returning the end Discord would read from.
+    #[cfg(not(target_os = "windows"))]
+    fn install(mut config: Config) -> std::os::unix::net::UnixStream {
+        let (pipe, discord) = std::os::unix::net::UnixStream::pair().unwrap();
+        discord.set_nonblocking(true).unwrap();
+        config.rich_client.pipe = Some(pipe);
+
+        unsafe {
+            CONFIG = Some(config);
+            INITIALIZED = true;
+        }
+        discord
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn uninstall() {
+        unsafe {
+            CONFIG = None;
+            INITIALIZED = false;
+        }
+    }
+
+    /// Drains whatever was written to Discord, returning the number of bytes.
+    #[cfg(not(target_os = "windows"))]
+    fn written(discord: &mut std::os::unix::net::UnixStream) -> usize {
+        use std::io::Read;
+
+        let mut buffer = [0; 4096];
+        let mut total = 0;
+        while let Ok(read @ 1..) = discord.read(&mut buffer) {
+            total += read;
+        }
+        total
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn raw(json: &str) -> CString {
+        CString::new(json).unwrap()
+    }
+
+    /// Arguments for a Rust buffer, with every other field left unset.
+    #[cfg(not(target_os = "windows"))]
+    fn rust_buffer(
+        filename: &CString,
+        filepath: &CString,
+        filetype: &CString,
+    ) -> PresenceArgs {
+        PresenceArgs {
+            filename: filename.as_ptr(),
+            filepath: filepath.as_ptr(),
+            filetype: filetype.as_ptr(),
+            cursor_position: null(),
+            problem_count: -1,
+            is_read_only: false,
+            is_streaming: false,
+            toolchain: null(),
+            symbol: null(),
+            is_empty_session: false,
+            languages: null(),
+            last_command: null(),
+            encoding: null(),
+            recent_files: null(),
+            line_count: -1,
+            should_clear: false,
+            colorscheme: null(),
+        }
+    }
+
+    /// Lets the next update through the rate limit right away.
+    #[cfg(not(target_os = "windows"))]
+    fn skip_rate_limit() {
+        if let Some(config) = unsafe { config() } {
+            config.rich_client.rate_limit = RateLimit::default();
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn shown_details() -> Option<String> {
+        unsafe { config() }.and_then(|config| {
+            config.rich_client.last_activity.as_ref()?.details.clone()
+        })
+    }
+
+    fn poll_all() -> Vec<i32> {
+        std::iter::from_fn(|| Some(poll_connection_event()))
+            .take_while(|&event| event != -1)
+            .collect()
+    }
+
+    #[test]
+    fn events_follow_a_connect_drop_reconnect_cycle() {
+        let _statics = STATICS.lock().unwrap_or_else(|e| e.into_inner());
+        unsafe { LAST_CONNECTED = None };
+        poll_all();
+        let connect = || Ok(default_config().rich_client);
+
+        unsafe {
+            CONFIG =
+                establish(Duration::ZERO, connect).map(|rich_client| Config {
+                    rich_client,
+                    ..default_config()
+                });
+            INITIALIZED = config().is_some();
+        }
+        disconnect();
+        assert!(unsafe { establish(Duration::ZERO, connect) }.is_some());
+        let refused = || {
+            Err(ConnectionError::Handshake {
+                code: Some(4000),
+                message: "Invalid Client ID".to_string(),
+            })
+        };
+        assert!(unsafe { establish(Duration::ZERO, refused) }.is_none());
+
+        assert_eq!(
+            poll_all(),
+            [
+                ConnectionEvent::Connected,
+                ConnectionEvent::Disconnected,
+                ConnectionEvent::Reconnecting,
+                ConnectionEvent::Connected,
+                ConnectionEvent::Reconnecting,
+                ConnectionEvent::HandshakeFailed,
+            ]
+            .map(|event| event as i32)
+        );
+        assert!(unsafe { config() }.is_none());
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn focus_mode_ignores_updates_and_restores_the_presence() {
+        let _statics = STATICS.lock().unwrap_or_else(|e| e.into_inner());
+        let mut discord = install(default_config());
+        let (filename, filepath, filetype) = (
+            raw("lib.rs"),
+            raw("/home/user/cord/src/lib.rs"),
+            raw("rust"),
+        );
+        let args = rust_buffer(&filename, &filepath, &filetype);
+
+        assert!(update_raw_presence(
+            raw(r#"{"details":"Editing main.rs"}"#).as_ptr()
+        ));
+        skip_rate_limit();
+        assert!(enter_focus_mode(raw("Focusing").as_ptr()));
+        assert_eq!(shown_details().as_deref(), Some("Focusing"));
+        written(&mut discord);
+
+        skip_rate_limit();
+        assert!(unsafe { update_presence(&args) });
+        assert!(unsafe {
+            update_presence_with_assets(
+                raw("lib.rs").as_ptr(),
+                raw("rust").as_ptr(),
+                raw("Rust").as_ptr(),
+                AssetType::Language as i32,
+                &args,
+            )
+        });
+        assert_eq!(written(&mut discord), 0);
+        assert_eq!(shown_details().as_deref(), Some("Focusing"));
+
+        skip_rate_limit();
+        assert!(exit_focus_mode());
+        assert!(written(&mut discord) > 0);
+        assert_eq!(shown_details().as_deref(), Some("Editing main.rs"));
+
+        skip_rate_limit();
+        assert!(unsafe { update_presence(&args) });
+        assert_ne!(shown_details().as_deref(), Some("Editing main.rs"));
+
+        uninstall();
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn the_ticker_needs_a_periodic_refresh_with_several_files() {
+        let _statics = STATICS.lock().unwrap_or_else(|e| e.into_inner());
+        install(Config {
+            recent_files_ticker: true,
+            recent_files: vec!["main.rs".to_string()],
+            ..default_config()
+        });
+        assert!(!needs_periodic_refresh());
+
+        if let Some(config) = unsafe { config() } {
+            config.recent_files.push("lib.rs".to_string());
+        }
+        assert!(needs_periodic_refresh());
+
+        uninstall();
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn the_first_update_waits_for_focus() {
+        let _statics = STATICS.lock().unwrap_or_else(|e| e.into_inner());
+        let mut discord = install(Config {
+            require_focus: true,
+            ..default_config()
+        });
+        unsafe { HAS_FOCUSED = false };
+        let (filename, filepath, filetype) = (
+            raw("lib.rs"),
+            raw("/home/user/cord/src/lib.rs"),
+            raw("rust"),
+        );
+        let args = rust_buffer(&filename, &filepath, &filetype);
+
+        assert!(unsafe { update_presence(&args) });
+        assert_eq!(written(&mut discord), 0);
+        assert_eq!(shown_details(), None);
+
+        focus_gained();
+        assert!(unsafe { update_presence(&args) });
+        assert!(written(&mut discord) > 0);
+        assert!(shown_details().is_some());
+
+        unsafe { HAS_FOCUSED = false };
+        uninstall();
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn nothing_is_sent_while_disabled() {
+        let _statics = STATICS.lock().unwrap_or_else(|e| e.into_inner());
+        let mut discord = install(default_config());
+        let activity = raw(r#"{"details":"Editing main.rs"}"#);
+
+        set_enabled(false);
+        assert!(written(&mut discord) > 0, "disabling clears the presence");
+
+        assert!(update_raw_presence(activity.as_ptr()));
+        clear_presence();
+        flush_presence();
+        assert!(enter_focus_mode(raw("Focusing").as_ptr()));
+        assert!(exit_focus_mode());
+        assert_eq!(written(&mut discord), 0);
+        assert!(unsafe { config() }.is_some_and(|config| {
+            config.rich_client.last_activity.is_none()
+                && config.rich_client.pending.is_none()
+                && !config.focus_mode
+        }));
+
+        set_enabled(true);
+        assert!(update_raw_presence(activity.as_ptr()));
+        assert!(written(&mut discord) > 0);
+
+        uninstall();
+    }
+}
@@ -0,0 +1,16 @@
+/// Every icon referenced by this mapping, used to list the asset URLs.
+pub const ICONS: [&str; 1] = ["default"];
+
+pub fn get(filetype: &str) -> Option<(&str, &str)> {
+    let dashboard = match filetype {
+        "alpha" => ("default", "Alpha"),
+        "dashboard" => ("default", "Dashboard"),
+        "ministarter" => ("default", "mini.starter"),
+        "startify" => ("default", "Startify"),
+        "snacks_dashboard" => ("default", "Snacks dashboard"),
+        "help" => ("default", "Help"),
+        _ => return None,
+    };
+
+    Some(dashboard)
+}
@@ -1,4 +1,7 @@
-pub fn get<'a>(filetype: &'a str) -> Option<(&'a str, &'a str)> {
+/// Every icon referenced by this mapping, used to list the asset URLs.
+pub const ICONS: [&str; 2] = ["default", "telescope"];
+
+pub fn get(filetype: &str) -> Option<(&str, &str)> {
     let file_browser = match filetype {
         "netrw" => ("default", "Netrw"),
         "TelescopePrompt" => ("telescope", "Telescope"),
@@ -1,10 +1,72 @@
-pub fn get<'a>(
-    filetype: &'a str,
+/// Every icon referenced by this mapping, used to list the asset URLs.
+pub const ICONS: [&str; 59] = [
+    "ahk",
+    "assembly",
+    "c",
+    "cargo",
+    "clojure",
+    "cpp",
+    "crystal",
+    "csharp",
+    "css",
+    "d",
+    "dart",
+    "docker",
+    "elixir",
+    "erlang",
+    "fsharp",
+    "git",
+    "gml",
+    "go",
+    "gradle",
+    "groovy",
+    "haskell",
+    "html",
+    "java",
+    "javascript",
+    "json",
+    "kotlin",
+    "latex",
+    "license",
+    "lisp",
+    "lua",
+    "markdown",
+    "nim",
+    "nix",
+    "ocaml",
+    "pascal",
+    "perl",
+    "php",
+    "postcss",
+    "powershell",
+    "python",
+    "r",
+    "react",
+    "ruby",
+    "rust",
+    "scala",
+    "scss",
+    "shell",
+    "sql",
+    "svelte",
+    "swift",
+    "text",
+    "toml",
+    "typescript",
+    "v",
+    "vim",
+    "vue",
+    "xml",
+    "yaml",
+    "zig",
+];
+
+pub fn get(
+    filetype: &str,
     filename: &str,
-) -> Option<(&'a str, &'a str)> {
+) -> Option<(&'static str, &'static str)> {
     let language = match filetype {
         "Cord.new" => ("text", "New file"),
-        "Cord.unknown" => ("text", filetype),
         "autohotkey" => ("ahk", "AutoHotkey"),
         "asm" => ("assembly", "Assembly"),
         "sh" => ("shell", "Shell script"),
@@ -85,15 +147,126 @@ pub fn get<'a>(
         "xml" => ("xml", "XML"),
         "yaml" => ("yaml", "YAML"),
         "zig" => ("zig", "Zig"),
-        _ => match filename.to_lowercase().rsplit_once('.') {
-            Some((_, extension)) => match extension {
-                "gml" => ("gml", "Game Maker Language"),
-                "pcss" | "postcss" => ("postcss", "PostCSS"),
-                _ => return None,
-            },
-            None => return None,
-        },
+        _ => return None,
     };
 
     Some(language)
 }
+
+pub fn get_by_extension(
+    extension: &str,
+) -> Option<(&'static str, &'static str)> {
+    let language = match extension.to_lowercase().as_str() {
+        "ahk" => ("ahk", "AutoHotkey"),
+        "asm" | "s" => ("assembly", "Assembly"),
+        "sh" | "bash" | "zsh" | "fish" => ("shell", "Shell script"),
+        "c" | "h" => ("c", "C "),
+        "clj" | "cljs" | "cljc" | "edn" => ("clojure", "Clojure"),
+        "cpp" | "cc" | "cxx" | "hpp" | "hh" | "hxx" => ("cpp", "C++"),
+        "cr" => ("crystal", "Crystal"),
+        "cs" => ("csharp", "C#"),
+        "css" => ("css", "CSS"),
+        "d" => ("d", "D "),
+        "dart" => ("dart", "Dart"),
+        "dockerfile" => ("docker", "Docker"),
+        "bat" | "cmd" => ("shell", "Batch"),
+        "ex" | "exs" => ("elixir", "Elixir"),
+        "erl" | "hrl" => ("erlang", "Erlang"),
+        "fs" | "fsi" | "fsx" => ("fsharp", "F#"),
+        "gml" => ("gml", "Game Maker Language"),
+        "go" => ("go", "Go"),
+        "gradle" => ("gradle", "Gradle"),
+        "groovy" => ("groovy", "Groovy"),
+        "hs" | "lhs" => ("haskell", "Haskell"),
+        "html" | "htm" => ("html", "HTML"),
+        "java" => ("java", "Java"),
+        "js" | "mjs" | "cjs" => ("javascript", "JavaScript"),
+        "jsx" => ("react", "JSX"),
+        "json" | "jsonc" => ("json", "JSON"),
+        "kt" | "kts" => ("kotlin", "Kotlin"),
+        "tex" => ("latex", "LaTeX"),
+        "lisp" | "cl" => ("lisp", "Lisp"),
+        "lua" => ("lua", "Lua"),
+        "md" | "markdown" => ("markdown", "Markdown"),
+        "nim" => ("nim", "Nim"),
+        "nix" => ("nix", "Nix"),
+        "ml" | "mli" => ("ocaml", "OCaml"),
+        "pas" => ("pascal", "Pascal"),
+        "pl" | "pm" => ("perl", "Perl"),
+        "php" => ("php", "PHP"),
+        "pcss" | "postcss" => ("postcss", "PostCSS"),
+        "ps1" | "psm1" => ("powershell", "PowerShell"),
+        "py" | "pyw" | "pyi" => ("python", "Python"),
+        "r" => ("r", "R "),
+        "rb" => ("ruby", "Ruby"),
+        "rs" => ("rust", "Rust"),
+        "scala" | "sc" => ("scala", "Scala"),
+        "sass" | "scss" => ("scss", "Sass"),
+        "sql" => ("sql", "SQL"),
+        "svelte" => ("svelte", "Svelte"),
+        "swift" => ("swift", "Swift"),
+        "txt" => ("text", "Plain Text"),
+        "toml" => ("toml", "TOML"),
+        "ts" | "mts" | "cts" => ("typescript", "TypeScript"),
+        "tsx" => ("react", "TSX"),
+        "v" => ("v", "V "),
+        "vim" => ("vim", "VimL"),
+        "vue" => ("vue", "Vue"),
+        "xml" => ("xml", "XML"),
+        "yaml" | "yml" => ("yaml", "YAML"),
+        "zig" => ("zig", "Zig"),
+        _ => return None,
+    };
+
+    Some(language)
+}
+
+/// Matches well-known files by name, whatever filetype Neovim reports for
+/// them.
+pub fn get_by_filename(filename: &str) -> Option<(&'static str, &'static str)> {
+    let language = match filename.to_lowercase().as_str() {
+        "dockerfile" | "containerfile" => ("docker", "Docker"),
+        "makefile" | "gnumakefile" => ("shell", "Makefile"),
+        "justfile" | ".justfile" => ("shell", "Justfile"),
+        "cmakelists.txt" => ("shell", "CMake"),
+        ".env" => ("shell", "Environment file"),
+        ".gitignore" | ".gitattributes" | ".gitmodules" => ("git", "Git"),
+        "license" | "license.md" | "license.txt" | "copying" => {
+            ("license", "License file")
+        }
+        "cargo.toml" | "cargo.lock" => ("cargo", "Cargo"),
+        _ => return None,
+    };
+
+    Some(language)
+}
+
+/// Matches multi-part extensions such as `.d.ts` or `.spec.ts`, trying the
+/// longest suffix of the file name first.
+pub fn get_by_compound_extension(
+    filename: &str,
+) -> Option<(&'static str, &'static str)> {
+    let filename = filename.to_lowercase();
+
+    filename
+        .match_indices('.')
+        .map(|(index, _)| &filename[index + 1..])
+        .find_map(|suffix| {
+            let language = match suffix {
+                "d.ts" | "d.mts" | "d.cts" => {
+                    ("typescript", "TypeScript declaration")
+                }
+                "test.ts" | "spec.ts" | "test.mts" | "spec.mts" => {
+                    ("typescript", "TypeScript test")
+                }
+                "test.tsx" | "spec.tsx" => ("react", "TSX test"),
+                "test.js" | "spec.js" | "test.mjs" | "spec.mjs"
+                | "test.cjs" | "spec.cjs" => ("javascript", "JavaScript test"),
+                "test.jsx" | "spec.jsx" => ("react", "JSX test"),
+                "spec.rb" => ("ruby", "Ruby spec"),
+                _ => return None,
+            };
+
+            Some(language)
+        })
+}
@@ -1,4 +1,7 @@
-pub fn get<'a>(filetype: &'a str) -> Option<(&'a str, &'a str)> {
+/// Every icon referenced by this mapping, used to list the asset URLs.
+pub const ICONS: [&str; 1] = ["default"];
+
+pub fn get(filetype: &str) -> Option<(&str, &str)> {
     let lsp_manager = match filetype {
         "lspinfo" => ("default", "LSP Config"),
         "mason" => ("default", "Mason"),
@@ -1,10 +1,35 @@
+pub mod dashboard;
 pub mod file_browser;
 pub mod language;
 pub mod lsp_manager;
 pub mod plugin_manager;
 
-pub fn get_by_filetype<'a>(filetype: &'a str, filename: &str) -> Filetype<'a> {
-    if let Some(language) = language::get(filetype, filename) {
+use std::collections::HashMap;
+
+/// Filetypes that plugins or older Neovim versions report under another
+/// name than the one the language mapping knows.
+const FILETYPE_ALIASES: [(&str, &str); 5] = [
+    ("tsx", "typescriptreact"),
+    ("typescript.tsx", "typescriptreact"),
+    ("typescript.jsx", "typescriptreact"),
+    ("jsx", "javascriptreact"),
+    ("javascript.jsx", "javascriptreact"),
+];
+
+pub fn get_by_filetype<'a>(
+    filetype: &'a str,
+    filename: &str,
+    aliases: &HashMap<String, String>,
+) -> Filetype<'a> {
+    if let Some(language) = language::get_by_filename(filename) {
+        return Filetype::Language(language.0, language.1);
+    }
+    if let Some(language) = language::get_by_compound_extension(filename) {
+        return Filetype::Language(language.0, language.1);
+    }
+    if let Some(language) = language::get(filetype, filename)
+        .or_else(|| language::get(&resolve_alias(filetype, aliases), filename))
+    {
         return Filetype::Language(language.0, language.1);
     }
     if let Some(file_browser) = file_browser::get(filetype) {
@@ -14,14 +39,81 @@ pub fn get_by_filetype<'a>(filetype: &'a str, filename: &str) -> Filetype<'a> {
         return Filetype::PluginManager(plugin_manager.0, plugin_manager.1);
     }
     if let Some(lsp_manager) = lsp_manager::get(filetype) {
-        return Filetype::LSP(lsp_manager.0, lsp_manager.1);
+        return Filetype::Lsp(lsp_manager.0, lsp_manager.1);
     }
-    Filetype::Language("text", filetype)
+    if let Some(dashboard) = dashboard::get(filetype) {
+        return Filetype::Dashboard(dashboard.0, dashboard.1);
+    }
+    if let Some(language) = filename
+        .rsplit_once('.')
+        .and_then(|(_, extension)| language_from_extension(extension))
+    {
+        return language;
+    }
+    Filetype::Unknown(filetype)
+}
+
+/// Lowercases the filetype and follows the user's aliases, then the built-in
+/// ones.
+fn resolve_alias(filetype: &str, aliases: &HashMap<String, String>) -> String {
+    let filetype = filetype.to_lowercase();
+    let target = aliases
+        .iter()
+        .find(|(alias, _)| alias.to_lowercase() == filetype)
+        .map(|(_, target)| target.as_str())
+        .or_else(|| {
+            FILETYPE_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == filetype)
+                .map(|(_, target)| *target)
+        });
+
+    target.map_or(filetype.clone(), str::to_lowercase)
+}
+
+pub fn language_from_extension(extension: &str) -> Option<Filetype<'static>> {
+    language::get_by_extension(extension.trim_start_matches('.'))
+        .map(|(icon, tooltip)| Filetype::Language(icon, tooltip))
 }
 
 pub enum Filetype<'a> {
     Language(&'a str, &'a str),
     FileBrowser(&'a str, &'a str),
     PluginManager(&'a str, &'a str),
-    LSP(&'a str, &'a str),
+    Lsp(&'a str, &'a str),
+    Dashboard(&'a str, &'a str),
+    Unknown(&'a str),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn icon(extension: &str) -> Option<&'static str> {
+        match language_from_extension(extension)? {
+            Filetype::Language(icon, _) => Some(icon),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn maps_common_extensions() {
+        assert_eq!(icon("rs"), Some("rust"));
+        assert_eq!(icon("py"), Some("python"));
+        assert_eq!(icon("lua"), Some("lua"));
+        assert_eq!(icon("cpp"), Some("cpp"));
+        assert_eq!(icon(".sh"), Some("shell"));
+    }
+
+    #[test]
+    fn ignores_the_case_of_extensions() {
+        assert_eq!(icon("RS"), Some("rust"));
+        assert_eq!(icon("Py"), Some("python"));
+    }
+
+    #[test]
+    fn rejects_unknown_extensions() {
+        assert!(language_from_extension("definitely-not-a-language").is_none());
+        assert!(language_from_extension("").is_none());
+    }
 }
@@ -1,4 +1,7 @@
-pub fn get<'a>(filetype: &'a str) -> Option<(&'a str, &'a str)> {
+/// Every icon referenced by this mapping, used to list the asset URLs.
+pub const ICONS: [&str; 1] = ["default"];
+
+pub fn get(filetype: &str) -> Option<(&str, &str)> {
     let plugin_manager = match filetype {
         "lazy" => ("default", "Lazy"),
         "pckr" => ("default", "Pckr"),
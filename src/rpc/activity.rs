@@ -12,23 +12,144 @@ pub struct ActivityButton {
     pub url: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityType {
+    Playing = 0,
+    Streaming = 1,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Activity {
+    pub kind: ActivityType,
+    pub url: Option<String>,
     pub details: Option<String>,
     pub state: Option<String>,
     pub assets: Option<ActivityAssets>,
     pub buttons: Option<Vec<ActivityButton>>,
     pub timestamp: Option<u128>,
+    /// Makes Discord count down to this time instead of, or next to, the
+    /// elapsed time since `timestamp`.
+    pub end_timestamp: Option<u128>,
 }
 
 impl Default for Activity {
     fn default() -> Self {
         Activity {
+            kind: ActivityType::Playing,
+            url: None,
             details: None,
             state: None,
             assets: None,
             buttons: None,
             timestamp: None,
+            end_timestamp: None,
+        }
+    }
+}
+impl ActivityAssets {
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.large_image.is_none()
+            && self.large_text.is_none()
+            && self.small_image.is_none()
+            && self.small_text.is_none()
+    }
+}
+
+impl Activity {
+    /// Compares every field except the start timestamp. The end timestamp
+    /// is a deadline set on purpose, so a change to it is kept.
+    pub fn eq_ignoring_timestamp(&self, other: &Activity) -> bool {
+        self.kind == other.kind
+            && self.url == other.url
+            && self.details == other.details
+            && self.state == other.state
+            && self.assets == other.assets
+            && self.buttons == other.buttons
+            && self.end_timestamp == other.end_timestamp
+    }
+
+    /// Drops empty fields and sub-objects that Discord would reject.
+    pub fn normalize(&mut self) {
+        drop_empty(&mut self.url);
+        drop_empty(&mut self.details);
+        drop_empty(&mut self.state);
+
+        if let Some(assets) = &mut self.assets {
+            drop_empty(&mut assets.large_image);
+            drop_empty(&mut assets.large_text);
+            drop_empty(&mut assets.small_image);
+            drop_empty(&mut assets.small_text);
+
+            if assets.is_empty() {
+                self.assets = None;
+            }
+        }
+
+        if self
+            .buttons
+            .as_ref()
+            .is_some_and(|buttons| buttons.is_empty())
+        {
+            self.buttons = None;
         }
     }
-}
\ No newline at end of file
+}
+
+#[inline(always)]
+fn drop_empty(field: &mut Option<String>) {
+    if field.as_ref().is_some_and(|value| value.is_empty()) {
+        *field = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_drops_an_all_empty_assets_object() {
+        let mut activity = Activity {
+            details: Some(String::new()),
+            assets: Some(ActivityAssets {
+                large_image: Some(String::new()),
+                large_text: None,
+                small_image: None,
+                small_text: Some(String::new()),
+            }),
+            ..Default::default()
+        };
+        activity.normalize();
+
+        assert_eq!(activity.details, None);
+        assert_eq!(activity.assets, None);
+    }
+
+    #[test]
+    fn normalize_drops_empty_buttons_and_keeps_the_rest() {
+        let mut activity = Activity {
+            state: Some("Workspace: cord".to_string()),
+            assets: Some(ActivityAssets {
+                large_image: Some("rust".to_string()),
+                large_text: Some(String::new()),
+                small_image: None,
+                small_text: None,
+            }),
+            buttons: Some(Vec::new()),
+            ..Default::default()
+        };
+        activity.normalize();
+
+        assert_eq!(activity.state.as_deref(), Some("Workspace: cord"));
+        assert_eq!(
+            activity.assets,
+            Some(ActivityAssets {
+                large_image: Some("rust".to_string()),
+                large_text: None,
+                small_image: None,
+                small_text: None,
+            })
+        );
+        assert_eq!(activity.buttons, None);
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+/// A small least-recently-used cache whose entries expire after a TTL.
+pub struct Cache<V> {
+    entries: Vec<(String, V, Instant)>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl<V: Clone> Cache<V> {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Cache {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            ttl,
+        }
+    }
+
+    pub fn get(&mut self, key: &str, now: Instant) -> Option<V> {
+        let index = self.entries.iter().position(|(k, _, _)| k == key)?;
+        let entry = self.entries.remove(index);
+        if now.duration_since(entry.2) >= self.ttl {
+            return None;
+        }
+
+        let value = entry.1.clone();
+        self.entries.push(entry);
+
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: String, value: V, now: Instant) {
+        self.invalidate(&key);
+        if self.entries.len() >= self.capacity {
+            self.entries.remove(0);
+        }
+
+        self.entries.push((key, value, now));
+    }
+
+    pub fn invalidate(&mut self, key: &str) {
+        self.entries.retain(|(k, _, _)| k != key);
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_reused_until_they_expire() {
+        let start = Instant::now();
+        let mut cache = Cache::new(2, Duration::from_secs(5));
+        cache.insert("/cord".to_string(), "main", start);
+
+        assert_eq!(
+            cache.get("/cord", start + Duration::from_secs(4)),
+            Some("main")
+        );
+        assert_eq!(cache.get("/cord", start + Duration::from_secs(5)), None);
+        // An expired entry is dropped, not kept for later
+        assert_eq!(cache.get("/cord", start), None);
+    }
+
+    #[test]
+    fn the_least_recently_used_entry_is_evicted() {
+        let now = Instant::now();
+        let mut cache = Cache::new(2, Duration::MAX);
+        cache.insert("a".to_string(), 1, now);
+        cache.insert("b".to_string(), 2, now);
+        assert_eq!(cache.get("a", now), Some(1));
+
+        cache.insert("c".to_string(), 3, now);
+        assert_eq!(cache.get("b", now), None);
+        assert_eq!(cache.get("a", now), Some(1));
+        assert_eq!(cache.get("c", now), Some(3));
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+
+use crate::ipc::client::{DISCORD_BUILD, IPC_PATH};
+use crate::json::serialize::{push_entry, push_escaped};
+use crate::Config;
+
+/// Writes a map as a JSON object with its keys sorted.
+fn push_map(json_str: &mut String, key: &str, map: &HashMap<String, String>) {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort();
+    json_str.push_str(&format!(",\"{}\":{{", key));
+    for (index, (name, value)) in entries.into_iter().enumerate() {
+        if index > 0 {
+            json_str.push(',');
+        }
+        push_escaped(json_str, name);
+        json_str.push(':');
+        push_escaped(json_str, value);
+    }
+    json_str.push('}');
+}
+
+fn push_list(json_str: &mut String, key: &str, list: &[String]) {
+    json_str.push_str(&format!(",\"{}\":[", key));
+    for (index, value) in list.iter().enumerate() {
+        if index > 0 {
+            json_str.push(',');
+        }
+        push_escaped(json_str, value);
+    }
+    json_str.push(']');
+}
+
+impl Config {
+    /// Writes the effective configuration with keys in a fixed order. When
+    /// `redact` is set, names, paths and URLs that could identify the user
+    /// are replaced with a placeholder.
+    pub fn to_normalized_json(&self, redact: bool) -> String {
+        let hide = |value: &str| {
+            if redact && !value.is_empty() {
+                "<redacted>".to_string()
+            } else {
+                value.to_string()
+            }
+        };
+        let mut json_str = String::from("{\"editor_image\":");
+
+        push_escaped(&mut json_str, &self.editor_image);
+        push_entry(&mut json_str, "editor_tooltip", &self.editor_tooltip);
+        push_entry(&mut json_str, "editor", &self.editor);
+        push_map(&mut json_str, "editor_images", &self.editor_images);
+        push_map(&mut json_str, "editor_tooltips", &self.editor_tooltips);
+        push_entry(&mut json_str, "idle_text", &self.idle_text);
+        push_entry(&mut json_str, "idle_tooltip", &self.idle_tooltip);
+        push_entry(&mut json_str, "idle_image", &self.idle_image);
+        push_entry(&mut json_str, "viewing_text", &self.viewing_text);
+        push_entry(&mut json_str, "editing_text", &self.editing_text);
+        push_entry(&mut json_str, "file_browser_text", &self.file_browser_text);
+        push_entry(
+            &mut json_str,
+            "plugin_manager_text",
+            &self.plugin_manager_text,
+        );
+        push_entry(&mut json_str, "lsp_manager_text", &self.lsp_manager_text);
+        push_entry(&mut json_str, "dashboard_text", &self.dashboard_text);
+        push_entry(&mut json_str, "unnamed_text", &self.unnamed_text);
+        push_entry(&mut json_str, "unnamed_icon", &self.unnamed_icon);
+        push_entry(&mut json_str, "workspace_text", &self.workspace_text);
+        push_entry(
+            &mut json_str,
+            "config_editing_text",
+            &self.config_editing_text,
+        );
+        push_entry(&mut json_str, "config_dir", &hide(&self.config_dir));
+        push_entry(&mut json_str, "streaming_text", &self.streaming_text);
+        push_entry(&mut json_str, "streaming_url", &hide(&self.streaming_url));
+        push_entry(
+            &mut json_str,
+            "languages_delimiter",
+            &self.languages_delimiter,
+        );
+        json_str
+            .push_str(&format!(",\"languages_max\":{}", self.languages_max));
+        json_str.push_str(&format!(
+            ",\"max_details_length\":{}",
+            self.max_details_length
+        ));
+        push_entry(
+            &mut json_str,
+            "cursor_position_format",
+            &self.cursor_position_format,
+        );
+        push_entry(&mut json_str, "problem_text", &self.problem_text);
+        push_entry(
+            &mut json_str,
+            "problem_text_singular",
+            &self.problem_text_singular,
+        );
+        push_entry(&mut json_str, "dirty_text", &self.dirty_text);
+        push_entry(&mut json_str, "workspace", &hide(&self.workspace));
+        push_entry(
+            &mut json_str,
+            "workspace_path",
+            &hide(&self.workspace_path),
+        );
+        push_entry(&mut json_str, "git_remote", &self.git_remote);
+        push_map(&mut json_str, "git_hosts", &self.git_hosts);
+        push_entry(&mut json_str, "assets_url", &hide(&self.assets_url));
+        push_entry(&mut json_str, "assets_version", &self.assets_version);
+
+        json_str.push_str(",\"buttons\":[");
+        for (index, button) in self.buttons.iter().enumerate() {
+            if index > 0 {
+                json_str.push(',');
+            }
+            json_str.push_str("{\"label\":");
+            push_escaped(&mut json_str, &button.label);
+            json_str.push_str(",\"url\":");
+            push_escaped(&mut json_str, &hide(&button.url));
+            json_str.push('}');
+        }
+        json_str.push(']');
+
+        push_map(&mut json_str, "icon_variants", &self.icon_variants);
+        push_map(&mut json_str, "filetype_aliases", &self.filetype_aliases);
+        push_list(&mut json_str, "workspace_markers", &self.workspace_markers);
+        push_list(&mut json_str, "lsp_root_markers", &self.lsp_root_markers);
+        let path_blacklist: Vec<String> =
+            self.path_blacklist.iter().map(|path| hide(path)).collect();
+        push_list(&mut json_str, "path_blacklist", &path_blacklist);
+        push_list(&mut json_str, "ignored_filetypes", &self.ignored_filetypes);
+        let ignored_files: Vec<String> =
+            self.ignored_files.iter().map(|file| hide(file)).collect();
+        push_list(&mut json_str, "ignored_files", &ignored_files);
+
+        for (key, value) in [
+            ("require_focus", self.require_focus),
+            ("use_package_root", self.use_package_root),
+            ("show_workspace_path", self.show_workspace_path),
+            ("relative_filename", self.relative_filename),
+            ("clear_on_empty", self.clear_on_empty),
+            (
+                "recompute_workspace_always",
+                self.recompute_workspace_always,
+            ),
+            ("sticky_code_presence", self.sticky_code_presence),
+            ("recent_files_ticker", self.recent_files_ticker),
+            ("git_changes", self.count_git_changes),
+            (
+                "ignore_timestamp_changes",
+                self.rich_client.ignore_timestamp_changes,
+            ),
+            ("check_responses", self.rich_client.check_responses),
+        ] {
+            json_str.push_str(&format!(",\"{}\":{}", key, value));
+        }
+        push_entry(&mut json_str, "layout", self.layout.as_str());
+        push_entry(
+            &mut json_str,
+            "on_missing_icon",
+            self.on_missing_icon.as_str(),
+        );
+        push_entry(&mut json_str, "default_icon", &self.default_icon);
+        push_entry(&mut json_str, "default_tooltip", &self.default_tooltip);
+        push_entry(
+            &mut json_str,
+            "timestamp_scope",
+            self.timestamp_scope.as_str(),
+        );
+        push_entry(
+            &mut json_str,
+            "workspace_privacy",
+            self.workspace_privacy.as_str(),
+        );
+        push_entry(
+            &mut json_str,
+            "workspace_placeholder",
+            &self.workspace_placeholder,
+        );
+        for (key, value) in [
+            ("readonly_debounce", self.readonly_debounce),
+            ("last_command_duration", self.last_command_duration),
+            ("git_cache_ttl", self.branch_cache.ttl()),
+        ] {
+            json_str.push_str(&format!(",\"{}\":{}", key, value.as_millis()));
+        }
+        let ipc_path = IPC_PATH.lock().ok().and_then(|path| path.clone());
+        push_entry(
+            &mut json_str,
+            "ipc_path",
+            &hide(ipc_path.as_deref().unwrap_or_default()),
+        );
+        let discord_build =
+            DISCORD_BUILD.lock().ok().and_then(|build| build.clone());
+        push_entry(
+            &mut json_str,
+            "discord_build",
+            discord_build.as_deref().unwrap_or_default(),
+        );
+
+        json_str.push('}');
+
+        json_str
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::deserialize::Value;
+    use crate::tests::default_config;
+    use crate::util::types::{Layout, WorkspacePrivacy};
+    use std::time::Duration;
+
+    const KEYS: [&str; 65] = [
+        "editor_image",
+        "editor_tooltip",
+        "editor",
+        "editor_images",
+        "editor_tooltips",
+        "idle_text",
+        "idle_tooltip",
+        "idle_image",
+        "viewing_text",
+        "editing_text",
+        "file_browser_text",
+        "plugin_manager_text",
+        "lsp_manager_text",
+        "dashboard_text",
+        "unnamed_text",
+        "unnamed_icon",
+        "workspace_text",
+        "config_editing_text",
+        "config_dir",
+        "streaming_text",
+        "streaming_url",
+        "languages_delimiter",
+        "languages_max",
+        "max_details_length",
+        "cursor_position_format",
+        "problem_text",
+        "problem_text_singular",
+        "dirty_text",
+        "workspace",
+        "workspace_path",
+        "git_remote",
+        "git_hosts",
+        "assets_url",
+        "assets_version",
+        "buttons",
+        "icon_variants",
+        "filetype_aliases",
+        "workspace_markers",
+        "lsp_root_markers",
+        "path_blacklist",
+        "ignored_filetypes",
+        "ignored_files",
+        "require_focus",
+        "use_package_root",
+        "show_workspace_path",
+        "relative_filename",
+        "clear_on_empty",
+        "recompute_workspace_always",
+        "sticky_code_presence",
+        "recent_files_ticker",
+        "git_changes",
+        "ignore_timestamp_changes",
+        "check_responses",
+        "layout",
+        "on_missing_icon",
+        "default_icon",
+        "default_tooltip",
+        "timestamp_scope",
+        "workspace_privacy",
+        "workspace_placeholder",
+        "readonly_debounce",
+        "last_command_duration",
+        "git_cache_ttl",
+        "ipc_path",
+        "discord_build",
+    ];
+
+    fn normalized(config: &Config, redact: bool) -> Value {
+        let json = config.to_normalized_json(redact);
+        let value = Value::parse(&json).expect("the config is valid JSON");
+        match &value {
+            Value::Object(entries) => assert_eq!(
+                entries
+                    .iter()
+                    .map(|(key, _)| key.as_str())
+                    .collect::<Vec<_>>(),
+                KEYS
+            ),
+            _ => panic!("the config is not an object: {}", json),
+        }
+
+        value
+    }
+
+    fn string<'a>(value: &'a Value, key: &str) -> &'a str {
+        value.get(key).and_then(Value::as_str).unwrap()
+    }
+
+    #[test]
+    fn defaults_have_every_key() {
+        let value = normalized(&default_config(), false);
+
+        assert_eq!(string(&value, "layout"), "default");
+        assert_eq!(string(&value, "workspace"), "cord");
+        assert_eq!(string(&value, "ipc_path"), "");
+        assert_eq!(
+            value.get("git_cache_ttl").and_then(Value::as_number),
+            Some("5000")
+        );
+        assert!(matches!(
+            value.get("workspace_markers"),
+            Some(Value::Array(markers)) if markers.len() == 3
+        ));
+    }
+
+    #[test]
+    fn merged_options_keep_the_same_shape() {
+        let mut config = default_config();
+        config.layout = Layout::Compact;
+        config.workspace_privacy = WorkspacePrivacy::Placeholder;
+        config.readonly_debounce = Duration::from_millis(750);
+        config
+            .editor_images
+            .insert("neovide".to_string(), "neovim".to_string());
+        config
+            .git_hosts
+            .insert("git.corp".to_string(), "gitlab".to_string());
+        config.lsp_root_markers = vec!["Cargo.toml".to_string()];
+        let value = normalized(&config, false);
+
+        assert_eq!(string(&value, "layout"), "compact");
+        assert_eq!(string(&value, "workspace_privacy"), "placeholder");
+        assert_eq!(
+            value.get("readonly_debounce").and_then(Value::as_number),
+            Some("750")
+        );
+        assert_eq!(
+            value
+                .get("editor_images")
+                .and_then(|images| images.get("neovide"))
+                .and_then(Value::as_str),
+            Some("neovim")
+        );
+        assert_eq!(
+            value
+                .get("git_hosts")
+                .and_then(|hosts| hosts.get("git.corp"))
+                .and_then(Value::as_str),
+            Some("gitlab")
+        );
+    }
+
+    #[test]
+    fn redacting_hides_the_workspace_and_its_path() {
+        let mut config = default_config();
+        config.path_blacklist = vec!["/home/user/secret/**".to_string()];
+        let value = normalized(&config, true);
+
+        assert_eq!(string(&value, "workspace"), "<redacted>");
+        assert_eq!(string(&value, "workspace_path"), "<redacted>");
+        assert_eq!(string(&value, "config_dir"), "<redacted>");
+        assert!(!config.to_normalized_json(true).contains("secret"));
+        // Empty values stay empty, so that unset options remain recognizable
+        assert_eq!(string(&value, "streaming_url"), "");
+    }
+}
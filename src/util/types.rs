@@ -2,7 +2,8 @@ pub enum AssetType {
     Language,
     FileBrowser,
     PluginManager,
-    LSP,
+    Lsp,
+    Dashboard,
 }
 
 impl AssetType {
@@ -12,8 +13,185 @@ impl AssetType {
             0 => Some(AssetType::Language),
             1 => Some(AssetType::FileBrowser),
             2 => Some(AssetType::PluginManager),
-            3 => Some(AssetType::LSP),
+            3 => Some(AssetType::Lsp),
+            4 => Some(AssetType::Dashboard),
             _ => None,
         }
     }
 }
+
+/// What to show as the large image for filetypes without a mapping.
+#[derive(Clone, Copy)]
+pub enum MissingIcon {
+    Text,
+    Editor,
+    None,
+}
+
+impl MissingIcon {
+    #[inline(always)]
+    pub fn from(value: &str) -> MissingIcon {
+        match value {
+            "editor" => MissingIcon::Editor,
+            "none" => MissingIcon::None,
+            _ => MissingIcon::Text,
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MissingIcon::Text => "text",
+            MissingIcon::Editor => "editor",
+            MissingIcon::None => "none",
+        }
+    }
+}
+
+/// Which start time the elapsed timer counts from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TimestampScope {
+    /// Since the session started, switching files keeps the timer running.
+    Session,
+    /// Since the current file was opened.
+    File,
+    /// Since the current workspace was entered, switching files within it
+    /// keeps the timer running.
+    Workspace,
+}
+
+impl TimestampScope {
+    #[inline(always)]
+    pub fn from(value: &str) -> TimestampScope {
+        match value {
+            "file" => TimestampScope::File,
+            "workspace" => TimestampScope::Workspace,
+            _ => TimestampScope::Session,
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimestampScope::Session => "session",
+            TimestampScope::File => "file",
+            TimestampScope::Workspace => "workspace",
+        }
+    }
+}
+
+/// How much of the workspace name is shown.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WorkspacePrivacy {
+    /// The workspace name is shown as is.
+    Off,
+    /// The workspace name is replaced with a fixed placeholder.
+    Placeholder,
+    /// The workspace name is left out along with the workspace line.
+    Hidden,
+}
+
+impl WorkspacePrivacy {
+    #[inline(always)]
+    pub fn from(value: &str) -> WorkspacePrivacy {
+        match value {
+            "placeholder" => WorkspacePrivacy::Placeholder,
+            "hidden" => WorkspacePrivacy::Hidden,
+            _ => WorkspacePrivacy::Off,
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkspacePrivacy::Off => "off",
+            WorkspacePrivacy::Placeholder => "placeholder",
+            WorkspacePrivacy::Hidden => "hidden",
+        }
+    }
+}
+
+pub struct PresenceParts {
+    pub details: String,
+    pub large_image: Option<String>,
+    pub large_text: String,
+}
+
+/// The content a `Layout` distributes over the two lines of an activity.
+pub struct PresenceLines {
+    /// What is done to which file, e.g. `Editing main.rs`.
+    pub file: String,
+    /// The line built from `text.workspace`.
+    pub workspace: Option<String>,
+    /// Project, branch and problems, listed on their own by `Compact`.
+    pub summary: Vec<String>,
+}
+
+/// The buffer a presence is built for.
+pub struct BufferInfo<'a> {
+    pub filename: &'a str,
+    pub filepath: &'a str,
+    pub filetype: &'a str,
+    pub is_read_only: bool,
+    pub cursor_position: Option<&'a str>,
+}
+
+/// What should happen to the presence for the current buffer.
+pub enum PresenceOutcome {
+    Present(PresenceParts),
+    /// The presence should be removed altogether.
+    Clear,
+    /// The presence that is currently shown should be kept.
+    NoChange,
+}
+
+/// How the file line and the workspace line are arranged over the two lines
+/// Discord shows, details first and state second.
+#[derive(Clone, Copy)]
+pub enum Layout {
+    /// File on the first line, workspace on the second.
+    Default,
+    /// Workspace on the first line, file on the second.
+    Swapped,
+    /// File on the first line, project, branch and problems on the second.
+    Compact,
+}
+
+impl Layout {
+    #[inline(always)]
+    pub fn from(value: &str, swap_fields: bool) -> Layout {
+        match value {
+            "default" => Layout::Default,
+            "swapped" => Layout::Swapped,
+            "compact" => Layout::Compact,
+            _ if swap_fields => Layout::Swapped,
+            _ => Layout::Default,
+        }
+    }
+
+    #[inline(always)]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Layout::Default => "default",
+            Layout::Swapped => "swapped",
+            Layout::Compact => "compact",
+        }
+    }
+
+    /// Returns the details and state for the given lines.
+    #[inline(always)]
+    pub fn arrange(
+        &self,
+        lines: PresenceLines,
+    ) -> (Option<String>, Option<String>) {
+        match self {
+            Layout::Default => (Some(lines.file), lines.workspace),
+            Layout::Swapped => (lines.workspace, Some(lines.file)),
+            Layout::Compact => (
+                Some(lines.file),
+                Some(lines.summary.join(" · "))
+                    .filter(|summary| !summary.is_empty()),
+            ),
+        }
+    }
+}